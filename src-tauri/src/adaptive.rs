@@ -0,0 +1,162 @@
+use crate::SETTING;
+use log::info;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{timeout, Duration};
+use url::Url;
+
+// 自动模式从 2 开始，连续成功且延迟正常时加 1，超时、429、连接重置时减半
+const START_LIMIT: f64 = 2.0;
+
+// 每个图片域名的自适应并发数
+struct HostState {
+    limit: f64,
+    in_flight: usize,
+    successes: usize,
+    notify: Arc<Notify>,
+}
+
+// 一次请求的结果
+pub enum Outcome {
+    // 成功，带请求耗时
+    Success(Duration),
+    // 超时、429、连接重置等说明请求过多
+    Throttled,
+    // 其他失败，不影响并发数
+    Failed,
+}
+
+static HOSTS: LazyLock<Mutex<HashMap<String, HostState>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+// 任务最近请求的域名，用于在进度事件中显示当前并发数
+static TASK_HOSTS: LazyLock<Mutex<HashMap<i32, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// 占用域名的一个并发位置，drop 时归还
+pub struct HostPermit {
+    host: String,
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        let mut hosts = HOSTS.lock().unwrap();
+        if let Some(state) = hosts.get_mut(&self.host) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+            state.notify.notify_waiters();
+        }
+    }
+}
+
+fn is_auto() -> bool {
+    SETTING.read().unwrap().auto_concurrent_img == "true"
+}
+
+// 自动模式下的上限为设置中的 concurrent_img
+fn max_limit() -> f64 {
+    SETTING
+        .read()
+        .unwrap()
+        .concurrent_img
+        .parse::<f64>()
+        .unwrap_or(10.0)
+        .max(1.0)
+}
+
+pub fn get_host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|x| x.host_str().map(String::from))
+        .unwrap_or_default()
+}
+
+// 等待域名的并发位置，未开启自动模式时返回 None 不限制
+pub async fn acquire_host(task_id: i32, url: &str) -> Option<HostPermit> {
+    if !is_auto() {
+        return None;
+    }
+    let host = get_host(url);
+    TASK_HOSTS.lock().unwrap().insert(task_id, host.clone());
+    let max = max_limit();
+    loop {
+        let notify = {
+            let mut hosts = HOSTS.lock().unwrap();
+            let state = hosts.entry(host.clone()).or_insert_with(|| HostState {
+                limit: START_LIMIT.min(max),
+                in_flight: 0,
+                successes: 0,
+                notify: Arc::new(Notify::new()),
+            });
+            state.limit = state.limit.min(max);
+            if state.in_flight < state.limit as usize {
+                state.in_flight += 1;
+                return Some(HostPermit { host });
+            }
+            state.notify.clone()
+        };
+        // 可能错过唤醒，最多等待 1 秒后重新检查
+        let _ = timeout(Duration::from_secs(1), notify.notified()).await;
+    }
+}
+
+// 记录请求结果并调整域名的并发数
+pub fn report(permit: &Option<HostPermit>, outcome: Outcome) {
+    let host = match permit {
+        Some(permit) => &permit.host,
+        None => return,
+    };
+    // 延迟超过超时时间的一半视为不健康
+    let healthy_latency = {
+        let setting = SETTING.read().unwrap();
+        Duration::from_secs(setting.img_timeout.parse::<u64>().unwrap_or(5)) / 2
+    };
+    let max = max_limit();
+    let mut hosts = HOSTS.lock().unwrap();
+    let state = match hosts.get_mut(host) {
+        Some(state) => state,
+        None => return,
+    };
+    match outcome {
+        Outcome::Success(latency) => {
+            if latency <= healthy_latency {
+                state.successes += 1;
+                // 每成功 limit 次加 1
+                if state.successes >= state.limit as usize && state.limit < max {
+                    state.limit = (state.limit + 1.0).min(max);
+                    state.successes = 0;
+                    info!("adaptive concurrency {} up: {}", host, state.limit);
+                    state.notify.notify_waiters();
+                }
+            } else {
+                state.successes = 0;
+            }
+        }
+        Outcome::Throttled => {
+            state.limit = (state.limit / 2.0).max(1.0);
+            state.successes = 0;
+            info!("adaptive concurrency {} down: {}", host, state.limit);
+        }
+        Outcome::Failed => {}
+    }
+}
+
+// 进度事件中显示的图片并发数，自动模式下为任务所用域名的当前值
+pub fn task_img_concurrency(task_id: i32) -> usize {
+    if !is_auto() {
+        return max_limit() as usize;
+    }
+    let host = match TASK_HOSTS.lock().unwrap().get(&task_id) {
+        Some(host) => host.clone(),
+        None => return START_LIMIT.min(max_limit()) as usize,
+    };
+    HOSTS
+        .lock()
+        .unwrap()
+        .get(&host)
+        .map(|x| x.limit as usize)
+        .unwrap_or(START_LIMIT.min(max_limit()) as usize)
+}
+
+pub fn forget_task(task_id: i32) {
+    TASK_HOSTS.lock().unwrap().remove(&task_id);
+}
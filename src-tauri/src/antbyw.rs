@@ -1,12 +1,18 @@
+use crate::cache::touch_cache;
+use crate::paths::app_paths;
 use crate::utils::{
-    cache_html, extract_number_manual, get_url_query, read_file_to_string, read_from_json,
-    retry_request, save_to_json, StatusCode,
+    cache_html, conditional_request, extract_number_manual, get_url_query, parse_chapter_number,
+    parse_number_ranges, read_file_to_string, read_from_json, save_to_json, set_url_query,
+    CacheValidators, Fetched, StatusCode,
 };
 use futures::future::join_all;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use tauri::{AppHandle, Emitter};
+use url::Url;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[allow(dead_code)]
@@ -33,7 +39,7 @@ impl HandleHtmlRes {
     pub fn new() -> HandleHtmlRes {
         HandleHtmlRes {
             code: StatusCode::Failed,
-            data: DataWrapper::HashMapData(HashMap::new()),
+            data: DataWrapper::CategoryData(Vec::new()),
             local: String::from(""),
             msg: String::from(""),
             author: String::from(""),
@@ -47,11 +53,51 @@ impl HandleHtmlRes {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum DataWrapper {
+    // 旧版本缓存的漫画页数据，没有保留类别顺序，只用于读取
     HashMapData(HashMap<String, Vec<CurrentElement>>),
+    CategoryData(Vec<ComicCategory>),
     VecAuthorData(Vec<AuthorElement>),
     VecData(Vec<Img>),
 }
 
+// 已知类别的默认顺序，旧缓存转换时使用
+const CATEGORY_ORDER: [&str; 3] = ["单行本", "单话", "番外篇"];
+
+impl DataWrapper {
+    // 按页面顺序返回漫画页的所有类别
+    pub fn categories(&self) -> Vec<ComicCategory> {
+        match self {
+            DataWrapper::CategoryData(data) => data.clone(),
+            DataWrapper::HashMapData(data) => {
+                let mut res: Vec<ComicCategory> = data
+                    .iter()
+                    .map(|(key, value)| ComicCategory {
+                        name: key.clone(),
+                        chapters: value.clone(),
+                    })
+                    .collect();
+                res.sort_by_key(|x| {
+                    (
+                        CATEGORY_ORDER
+                            .iter()
+                            .position(|&c| c == x.name)
+                            .unwrap_or(CATEGORY_ORDER.len()),
+                        x.name.clone(),
+                    )
+                });
+                res
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ComicCategory {
+    pub name: String,
+    pub chapters: Vec<CurrentElement>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AuthorElement {
     pub url: String,
@@ -75,35 +121,189 @@ pub struct CurrentElement {
 pub struct Img {
     pub href: String,
     pub done: bool,
+    // 备用图片地址，主地址下载失败时使用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_href: Option<String>,
 }
 
-#[allow(dead_code)]
-pub struct JuanHuaFanwaiCount {
-    juan: usize,
-    hua: usize,
-    fanwai: usize,
-    all: usize,
+// 添加任务时的章节选择，三个字段同时存在时取交集
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ChapterSelection {
+    // 章节号范围，例如 "1-20,35,40-"
+    pub range: Option<String>,
+    // 只下载最新的 N 个
+    pub latest: Option<usize>,
+    // 指定的 CurrentElement.href 列表
+    pub hrefs: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterPreview {
+    pub name: String,
+    pub href: String,
+    pub count: usize,
+    pub done: bool,
 }
 
-pub async fn handle_html(url: String, dl_type: String, app: &AppHandle) -> HandleHtmlRes {
-    info!("handle_html invoke url: {}, dl_type: {}", &url, &dl_type);
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterPreviewGroup {
+    pub category: String,
+    pub chapters: Vec<ChapterPreview>,
+}
+
+// 按选择条件过滤章节，value 已按章节号排好序
+pub fn select_chapters(
+    value: &Vec<CurrentElement>,
+    selection: &ChapterSelection,
+) -> Vec<CurrentElement> {
+    let mut res: Vec<CurrentElement> = value.clone();
+
+    if let Some(range) = &selection.range {
+        let ranges = parse_number_ranges(range);
+        if !ranges.is_empty() {
+            res.retain(|x| {
+                let number = parse_chapter_number(&x.name).number().unwrap_or(0.0);
+                ranges
+                    .iter()
+                    .any(|(start, end)| number >= *start && end.is_none_or(|e| number <= e))
+            });
+        }
+    }
+    if let Some(hrefs) = &selection.hrefs {
+        res.retain(|x| hrefs.contains(&x.href));
+    }
+    if let Some(latest) = selection.latest {
+        if res.len() > latest {
+            res = res.split_off(res.len() - latest);
+        }
+    }
+    res
+}
+
+// 添加任务前预览漫画页的章节列表和图片数量
+pub fn preview_chapters(res: &HandleHtmlRes) -> Vec<ChapterPreviewGroup> {
+    let mut preview = Vec::new();
+    for category in res.data.categories() {
+        preview.push(ChapterPreviewGroup {
+            category: category.name,
+            chapters: category
+                .chapters
+                .iter()
+                .map(|x| ChapterPreview {
+                    name: x.name.clone(),
+                    href: x.href.clone(),
+                    count: x.count,
+                    done: x.done,
+                })
+                .collect(),
+        });
+    }
+    preview
+}
+
+// 读取页面缓存的方式
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheMode {
+    // 有缓存时直接使用
+    #[default]
+    Prefer,
+    // 带上保存的 ETag/Last-Modified 重新请求，页面没有变化时继续使用缓存
+    Revalidate,
+    // 忽略缓存重新下载
+    Bypass,
+}
+
+// html 缓存的校验信息保存在同名的 .meta 文件
+pub fn validators_path(cache_path: &Path) -> PathBuf {
+    let mut name = cache_path.as_os_str().to_owned();
+    name.push(".meta");
+    PathBuf::from(name)
+}
+
+fn read_cache(cache_path: &Path) -> Option<String> {
+    touch_cache(cache_path);
+    read_file_to_string(cache_path.to_str().unwrap()).ok()
+}
+
+// 按 mode 读取 html 缓存或者重新下载，下载后更新缓存和校验信息
+async fn cached_html(url: &str, cache_path: &Path, mode: CacheMode) -> Option<String> {
+    let cached = cache_path.exists();
+    if cached && mode == CacheMode::Prefer {
+        return read_cache(cache_path);
+    }
+
+    let meta_path = validators_path(cache_path);
+    let validators = if cached && mode == CacheMode::Revalidate {
+        read_from_json::<CacheValidators>(meta_path.to_str().unwrap()).unwrap_or_default()
+    } else {
+        CacheValidators::default()
+    };
+    match conditional_request(url, &validators, 5).await {
+        Ok(Fetched::NotModified) => {
+            info!("not modified, use cache: {}", cache_path.display());
+            read_cache(cache_path)
+        }
+        Ok(Fetched::Modified(html_content, validators)) => {
+            if let Err(e) = cache_html(&html_content, cache_path.to_path_buf()) {
+                error!("{}", e.to_string());
+            }
+            let saved = if validators.is_empty() {
+                fs::remove_file(&meta_path).or_else(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(e),
+                })
+            } else {
+                save_to_json(&validators, meta_path.to_str().unwrap())
+            };
+            if let Err(e) = saved {
+                error!("cache validators failed: {} {}", meta_path.display(), e);
+            }
+            Some(html_content)
+        }
+        Err(e) => {
+            error!("download {} failed: {}", url, e);
+            // 重新验证失败时继续使用旧缓存
+            if cached && mode == CacheMode::Revalidate {
+                warn!(
+                    "revalidate failed, use stale cache: {}",
+                    cache_path.display()
+                );
+                read_cache(cache_path)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+pub async fn handle_html(
+    url: String,
+    dl_type: String,
+    mode: CacheMode,
+    app: &AppHandle,
+) -> HandleHtmlRes {
+    info!(
+        "handle_html invoke url: {}, dl_type: {}, mode: {:?}",
+        &url, &dl_type, mode
+    );
 
     match dl_type.clone().as_str() {
         "juan" | "hua" | "fanwai" | "juan_hua_fanwai" => {
-            let res = handle_comic_html(url.clone(), String::from(""), app).await;
+            let res = handle_comic_html(url.clone(), String::from(""), mode, app).await;
             res
         }
         "current" => {
-            let res = handle_current_html(url.clone()).await;
+            let res = handle_current_html(url.clone(), mode).await;
             res
         }
         "author" => {
-            let res = handle_author_html(url.clone(), app).await;
+            let res = handle_author_html(url.clone(), mode, app).await;
             res
         }
         _ => HandleHtmlRes {
             code: StatusCode::Failed,
-            data: DataWrapper::HashMapData(HashMap::new()),
+            data: DataWrapper::CategoryData(Vec::new()),
             local: String::from(""),
             msg: String::from("no matched dl_type"),
             author: String::from(""),
@@ -115,24 +315,19 @@ pub async fn handle_html(url: String, dl_type: String, app: &AppHandle) -> Handl
     }
 }
 
-pub async fn handle_author_html(url: String, app: &AppHandle) -> HandleHtmlRes {
+pub async fn handle_author_html(url: String, mode: CacheMode, app: &AppHandle) -> HandleHtmlRes {
     // 获取作者页zz_name
     let zz_name = get_url_query(url.clone(), String::from("zz_name"));
-    let page = get_url_query(url.clone(), String::from("page"));
-
-    let home_dir = home::home_dir().unwrap();
-    let author_html_cache_path = home_dir.join(format!(
-        ".comic_dl_tauri/html_cache/antbyw_author_{}_{}.htmlcache",
-        &zz_name, &page,
-    ));
-    let author_json_cache_path = home_dir.join(format!(
-        ".comic_dl_tauri/json_cache/antbyw_author_{}_{}.json",
-        &zz_name, &page
-    ));
+
+    // 所有分页合并成一个作者缓存
+    let author_json_cache_path = app_paths()
+        .json_cache_dir()
+        .join(format!("antbyw_author_{}.json", &zz_name));
 
     let mut json_data_from_read = Some(HandleHtmlRes::new());
-    // 如果已经存在author cache json 直接返回
-    if author_json_cache_path.exists() {
+    // 如果已经存在author cache json 直接返回，刷新时忽略 json 缓存
+    if mode == CacheMode::Prefer && author_json_cache_path.exists() {
+        touch_cache(&author_json_cache_path);
         match read_from_json::<HandleHtmlRes>(author_json_cache_path.to_str().unwrap()) {
             Ok(res) => {
                 json_data_from_read = Some(res.clone());
@@ -163,17 +358,20 @@ pub async fn handle_author_html(url: String, app: &AppHandle) -> HandleHtmlRes {
         };
     }
 
-    // 先读缓存，如果没有再去下载漫画页html然后缓存到本地
-    let html_content;
-    if author_html_cache_path.exists() {
-        html_content = read_file_to_string(author_html_cache_path.to_str().unwrap()).unwrap();
-    } else {
-        // 请求漫画页面html
-        match retry_request(&url, 5).await {
-            Ok(s) => {
-                html_content = s;
-            }
-            Err(_) => {
+    let mut json_data: Vec<AuthorElement> = Vec::new();
+    if let Some(data) = json_data_from_read {
+        json_data = if let DataWrapper::VecAuthorData(temp_data) = data.data {
+            temp_data
+        } else {
+            Vec::new()
+        };
+    }
+
+    if json_data.is_empty() {
+        let first_page = get_url_query(url.clone(), String::from("page"));
+        let first_html = match author_page_html(&url, &zz_name, &first_page, mode).await {
+            Some(s) => s,
+            None => {
                 return HandleHtmlRes {
                     code: StatusCode::Failed,
                     msg: String::from("download author html failed!"),
@@ -188,52 +386,42 @@ pub async fn handle_author_html(url: String, app: &AppHandle) -> HandleHtmlRes {
             }
         };
 
-        // 缓存漫画页面html
-        if let Err(e) = cache_html(&html_content, author_html_cache_path) {
-            error!(
-                "cache {} failed: {}",
-                format!("antbyw_author_{}_{}.htmlcache", &zz_name, &page),
-                e
-            );
-        }
-    }
-
-    let mut json_data: Vec<AuthorElement> = Vec::new();
-    if let Some(data) = json_data_from_read {
-        json_data = if let DataWrapper::VecAuthorData(temp_data) = data.data {
-            temp_data
-        } else {
-            Vec::new()
-        };
-    }
-
-    if json_data.is_empty() {
-        let document = scraper::Html::parse_document(&html_content);
-        let comic_selector = scraper::Selector::parse(".uk-card-media-top.uk-inline a").unwrap();
-        let comic_name_selector =
-            scraper::Selector::parse(".uk-card.uk-text-center .mt5.mb5.uk-text-truncate a")
-                .unwrap();
-        let comic_urls = document
-            .select(&comic_selector)
-            .map(|x| {
-                let mut comic_url = x.attr("href").unwrap().to_string();
-                comic_url.remove(0);
-                let complete_url = format!("https://www.antbyw.com{}", comic_url);
-                complete_url
-            })
-            .collect::<Vec<_>>();
-        let comic_names = document
-            .select(&comic_name_selector)
-            .map(|x| x.inner_html())
-            .collect::<Vec<_>>();
-
-        for (url, name) in comic_urls.iter().zip(comic_names.iter()) {
-            json_data.push(AuthorElement {
-                url: url.to_string(),
-                comic_name: name.to_string(),
-                local: String::from(""),
-                done: false,
-            });
+        // 根据分页链接找到最大页数，依次处理每一页
+        let max_page = author_max_page(&first_html).max(first_page.parse::<u32>().unwrap_or(1));
+        info!("author {} max_page: {}", &zz_name, max_page);
+        for page in 1..=max_page {
+            let page_str = page.to_string();
+            let html_content = if page_str == first_page || (first_page.is_empty() && page == 1) {
+                Some(first_html.clone())
+            } else {
+                let page_url = set_url_query(&url, "page", &page_str);
+                author_page_html(&page_url, &zz_name, &page_str, mode).await
+            };
+            match html_content {
+                Some(html_content) => {
+                    for ele in parse_author_comics(&html_content) {
+                        if !json_data.iter().any(|x| x.url == ele.url) {
+                            json_data.push(ele);
+                        }
+                    }
+                }
+                None => {
+                    error!("download author page failed: {} page: {}", &zz_name, page);
+                    return HandleHtmlRes {
+                        code: StatusCode::Failed,
+                        msg: format!("download author page {} html failed!", page),
+                        data: DataWrapper::VecAuthorData(Vec::new()),
+                        local: String::from(""),
+                        author: String::from(""),
+                        comic_name: String::from(""),
+                        current_name: String::from(""),
+                        current_count: 0,
+                        done: false,
+                    };
+                }
+            }
+            let progress = format!("page {}/{}", page, max_page);
+            app.emit("author_progress", progress).unwrap();
         }
     }
 
@@ -245,7 +433,9 @@ pub async fn handle_author_html(url: String, app: &AppHandle) -> HandleHtmlRes {
     for (i, data) in json_data.iter().enumerate() {
         let mut temp = data.clone();
         if !data.done {
-            let comic_res = handle_comic_html(data.url.clone(), zz_name.clone(), app).await;
+            // 刷新作者只重新抓取作者页，已缓存的漫画需要单独刷新
+            let comic_res =
+                handle_comic_html(data.url.clone(), zz_name.clone(), CacheMode::Prefer, app).await;
             if comic_res.done {
                 temp.local = comic_res.local;
                 temp.done = true;
@@ -277,7 +467,7 @@ pub async fn handle_author_html(url: String, app: &AppHandle) -> HandleHtmlRes {
     if let Err(e) = save_to_json(&res, &author_json_cache_path.to_str().unwrap()) {
         error!(
             "cache author json {}: {} ",
-            format!("antbyw_author_{}_{}.json", &zz_name, &page),
+            format!("antbyw_author_{}.json", &zz_name),
             e,
         );
         return HandleHtmlRes {
@@ -296,24 +486,132 @@ pub async fn handle_author_html(url: String, app: &AppHandle) -> HandleHtmlRes {
     res
 }
 
-pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) -> HandleHtmlRes {
+// 先读缓存，如果没有再去下载作者页某一页的html然后缓存到本地
+async fn author_page_html(url: &str, zz_name: &str, page: &str, mode: CacheMode) -> Option<String> {
+    let author_html_cache_path = app_paths()
+        .html_cache_dir()
+        .join(format!("antbyw_author_{}_{}.htmlcache", zz_name, page,));
+    cached_html(url, &author_html_cache_path, mode).await
+}
+
+// 作者页分页链接中的最大页数，没有分页时为 1
+fn author_max_page(html_content: &str) -> u32 {
+    let document = scraper::Html::parse_document(html_content);
+    let page_selector = scraper::Selector::parse(".uk-pagination a").unwrap();
+    document
+        .select(&page_selector)
+        .filter_map(|x| {
+            let href = x.attr("href")?;
+            let complete_url = Url::parse("https://www.antbyw.com/")
+                .ok()?
+                .join(href)
+                .ok()?;
+            get_url_query(complete_url.to_string(), String::from("page"))
+                .parse::<u32>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(1)
+}
+
+// 解析作者页某一页中的所有漫画
+fn parse_author_comics(html_content: &str) -> Vec<AuthorElement> {
+    let document = scraper::Html::parse_document(html_content);
+    let comic_selector = scraper::Selector::parse(".uk-card-media-top.uk-inline a").unwrap();
+    let comic_name_selector =
+        scraper::Selector::parse(".uk-card.uk-text-center .mt5.mb5.uk-text-truncate a").unwrap();
+    let comic_urls = document
+        .select(&comic_selector)
+        .map(|x| {
+            let mut comic_url = x.attr("href").unwrap().to_string();
+            comic_url.remove(0);
+            let complete_url = format!("https://www.antbyw.com{}", comic_url);
+            complete_url
+        })
+        .collect::<Vec<_>>();
+    let comic_names = document
+        .select(&comic_name_selector)
+        .map(|x| x.inner_html())
+        .collect::<Vec<_>>();
+
+    comic_urls
+        .iter()
+        .zip(comic_names.iter())
+        .map(|(url, name)| AuthorElement {
+            url: url.to_string(),
+            comic_name: name.to_string(),
+            local: String::from(""),
+            done: false,
+        })
+        .collect()
+}
+
+// 旧版本按分页保存的作者 json 缓存 antbyw_author_{zz_name}_{page}.json，现在合并成 antbyw_author_{zz_name}.json
+// 两种缓存的 author 都是 zz_name，文件名是 author 加页码的才是旧缓存
+pub fn remove_old_author_caches() {
+    let dir = app_paths().json_cache_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!(
+                "remove_old_author_caches read_dir failed: {} e: {}",
+                dir.display(),
+                e
+            );
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(stem) = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_prefix("antbyw_author_"))
+            .and_then(|x| x.strip_suffix(".json"))
+        else {
+            continue;
+        };
+        let Ok(res) = read_from_json::<HandleHtmlRes>(path.to_str().unwrap_or_default()) else {
+            continue;
+        };
+        let is_old = stem
+            .strip_prefix(res.author.as_str())
+            .and_then(|x| x.strip_prefix('_'))
+            .is_some_and(|page| !page.is_empty() && page.chars().all(|c| c.is_ascii_digit()));
+        if !is_old {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(_) => info!("remove old author cache: {}", path.display()),
+            Err(e) => error!(
+                "remove old author cache failed: {} e: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+}
+
+pub async fn handle_comic_html(
+    url: String,
+    author: String,
+    mode: CacheMode,
+    app: &AppHandle,
+) -> HandleHtmlRes {
     // 获取漫画页面 kuid
     let kuid = get_url_query(url.clone(), String::from("kuid"));
-    // 系统的用户目录
-    let home_dir = home::home_dir().unwrap();
-    let comic_html_cache_path = home_dir.join(format!(
-        ".comic_dl_tauri/html_cache/antbyw_comic_{}.htmlcache",
-        &kuid
-    ));
-    let comic_json_cache_path = home_dir.join(format!(
-        ".comic_dl_tauri/json_cache/antbyw_comic_{}.json",
-        &kuid
-    ));
+    let comic_html_cache_path = app_paths()
+        .html_cache_dir()
+        .join(format!("antbyw_comic_{}.htmlcache", &kuid));
+    let comic_json_cache_path = app_paths()
+        .json_cache_dir()
+        .join(format!("antbyw_comic_{}.json", &kuid));
 
     let mut json_data_from_read = Some(HandleHtmlRes::new());
 
-    // 如果已经存在current cache json 直接返回
-    if comic_json_cache_path.exists() {
+    // 如果已经存在current cache json 直接返回，刷新时忽略 json 缓存重新解析
+    if mode == CacheMode::Prefer && comic_json_cache_path.exists() {
+        touch_cache(&comic_json_cache_path);
         match read_from_json::<HandleHtmlRes>(comic_json_cache_path.to_str().unwrap()) {
             Ok(res) => {
                 json_data_from_read = Some(res.clone());
@@ -321,17 +619,10 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
                     return res;
                 } else {
                     warn!("comic cache not done!");
-                    if let DataWrapper::HashMapData(data) = res.data.clone() {
-                        for (key, value) in data.iter() {
-                            let all = value.len();
-                            let mut current: usize = 0;
-                            for i in value.iter() {
-                                if i.done {
-                                    current += 1;
-                                }
-                            }
-                            warn!("comic cache {}: {}/{}", key, current, all);
-                        }
+                    for category in res.data.categories() {
+                        let all = category.chapters.len();
+                        let current = category.chapters.iter().filter(|x| x.done).count();
+                        warn!("comic cache {}: {}/{}", category.name, current, all);
                     }
                 }
             }
@@ -345,52 +636,31 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
     }
 
     // 先读缓存，如果没有再去下载漫画页html然后缓存到本地
-    let html_content;
-    if comic_html_cache_path.exists() {
-        html_content = read_file_to_string(comic_html_cache_path.to_str().unwrap()).unwrap();
-    } else {
-        // 请求漫画页面html
-        match retry_request(&url, 5).await {
-            Ok(s) => {
-                html_content = s;
-            }
-            Err(_) => {
-                return HandleHtmlRes {
-                    code: StatusCode::Failed,
-                    msg: String::from("download comic html failed!"),
-                    data: DataWrapper::HashMapData(HashMap::new()),
-                    local: String::from(""),
-                    author: String::from(""),
-                    comic_name: String::from(""),
-                    current_name: String::from(""),
-                    current_count: 0,
-                    done: false,
-                };
-            }
-        };
-
-        // 缓存漫画页面html
-        if let Err(e) = cache_html(&html_content, comic_html_cache_path) {
-            error!(
-                "cache {} failed: {}",
-                format!("antbyw_{}.htmlcache", &kuid),
-                e
-            );
+    let html_content = match cached_html(&url, &comic_html_cache_path, mode).await {
+        Some(s) => s,
+        None => {
+            return HandleHtmlRes {
+                code: StatusCode::Failed,
+                msg: String::from("download comic html failed!"),
+                data: DataWrapper::CategoryData(Vec::new()),
+                local: String::from(""),
+                author: String::from(""),
+                comic_name: String::from(""),
+                current_name: String::from(""),
+                current_count: 0,
+                done: false,
+            };
         }
-    }
+    };
 
     let mut title_vec = Vec::new();
     let mut content_vec = Vec::new();
-    let mut json_data: HashMap<String, Vec<CurrentElement>> = HashMap::new();
+    let mut json_data: Vec<ComicCategory> = Vec::new();
     let mut comic_name: String = String::from("");
     // let mut author: String = String::from("");
 
     if let Some(data) = json_data_from_read {
-        json_data = if let DataWrapper::HashMapData(temp_data) = data.data {
-            temp_data
-        } else {
-            HashMap::new()
-        };
+        json_data = data.data.categories();
         comic_name = data.comic_name;
     }
 
@@ -410,7 +680,7 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
         // let author_temp: Vec<_> = document.select(&author_selector).to_owned().collect();
         // author = author_temp.first().unwrap().inner_html();
 
-        // 获取三个 title 单行本 单话 番外篇
+        // 获取所有类别 title，例如 单行本 单话 番外篇 短篇
         let juan_hua_fanwai_title_selector =
             &scraper::Selector::parse("h3.uk-alert-warning").unwrap();
         let juan_hua_fanwai_title = document.select(juan_hua_fanwai_title_selector).to_owned();
@@ -445,74 +715,42 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
                 href_vec.push(temp);
             }
             href_vec.sort_by(|a, b| {
-                let a_number = extract_number_manual(&a.name).unwrap();
-                let b_number = extract_number_manual(&b.name).unwrap();
-                a_number.cmp(&b_number)
+                let a_number = parse_chapter_number(&a.name);
+                let b_number = parse_chapter_number(&b.name);
+                a_number.cmp(&b_number).then_with(|| a.name.cmp(&b.name))
             });
             content_vec.push(href_vec);
         }
 
-        // json_data最终处理好的HashMap结构
-        // {
-        //     "单话": [{name: "第1话", href: ""}],
-        //     "单行本": [{name: "第1卷", href: ""}],
-        //     "番外篇": [{name: "番外1", href: ""}],
-        // }
+        // json_data最终处理好的结构，保留页面上的类别顺序
+        // [
+        //     {name: "单行本", chapters: [{name: "第1卷", href: ""}]},
+        //     {name: "单话", chapters: [{name: "第1话", href: ""}]},
+        //     {name: "番外篇", chapters: [{name: "番外1", href: ""}]},
+        // ]
         for (title, content) in title_vec.iter().zip(content_vec.iter()) {
-            json_data.insert(title.clone(), content.clone());
+            json_data.push(ComicCategory {
+                name: title.clone(),
+                chapters: content.clone(),
+            });
         }
     }
 
     info!("comic json_data: {:?}", json_data);
     // 并发获取comic（juan hua fanwai）中所有的 current页的图片
-    let mut new_json_data: HashMap<String, Vec<CurrentElement>> = HashMap::new();
-    let juan_count = json_data
-        .get("单行本")
-        .unwrap_or(&Vec::new() as &Vec<CurrentElement>)
-        .len();
-    let hua_count = json_data
-        .get("单话")
-        .unwrap_or(&Vec::new() as &Vec<CurrentElement>)
-        .len();
-    let fanwai_count = json_data
-        .get("番外篇")
-        .unwrap_or(&Vec::new() as &Vec<CurrentElement>)
-        .len();
-
-    let all_type_count = JuanHuaFanwaiCount {
-        juan: juan_count,
-        hua: hua_count,
-        fanwai: fanwai_count,
-        all: juan_count + hua_count + fanwai_count,
-    };
-
-    let all_count = all_type_count.all;
+    let mut new_json_data: Vec<ComicCategory> = Vec::new();
+    let all_count: usize = json_data.iter().map(|x| x.chapters.len()).sum();
     let mut done_count: usize = 0;
 
-    for (key, value) in json_data.iter() {
-        let task_count: usize = value.len();
-        const GROUP_SIZE: usize = 5;
-
-        let mut concurrent_results: Vec<HandleHtmlRes> = Vec::new();
-
-        let all_tasks: Vec<String> = (1..=task_count)
-            .map(|index| value.get(index - 1).unwrap().href.clone())
-            .collect();
-        for group in all_tasks.chunks(GROUP_SIZE) {
-            let group_tasks = group
-                .iter()
-                .map(|current_url| handle_current_html(current_url.to_string().clone()));
-            let results: Vec<HandleHtmlRes> = join_all(group_tasks).await;
-            results.iter().for_each(|x| {
-                if x.done {
-                    done_count += 1;
-                }
-            });
-
+    for category in json_data.iter() {
+        let value = &category.chapters;
+        let all_tasks: Vec<String> = value.iter().map(|x| x.href.clone()).collect();
+        let concurrent_results = fetch_chapter_pages(&all_tasks, mode, |results| {
+            done_count += results.iter().filter(|x| x.done).count();
             let progress = format!("{}/{}", done_count, all_count);
             app.emit("comic_progress", progress).unwrap();
-            concurrent_results.extend(results);
-        }
+        })
+        .await;
 
         let mut new_value: Vec<CurrentElement> = Vec::new();
 
@@ -533,19 +771,22 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
             new_value.push(temp);
         }
 
-        new_json_data.insert(key.to_string(), new_value);
+        new_json_data.push(ComicCategory {
+            name: category.name.clone(),
+            chapters: new_value,
+        });
     }
 
     let done: bool;
     let mut temp_count: usize = 0;
-    for (_key, value) in new_json_data.iter() {
-        for data in value.iter() {
+    for category in new_json_data.iter() {
+        for data in category.chapters.iter() {
             if data.done {
                 temp_count += 1;
             }
         }
     }
-    if temp_count == all_type_count.all {
+    if temp_count == all_count {
         done = true;
     } else {
         done = false;
@@ -554,7 +795,7 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
     let res = HandleHtmlRes {
         code: StatusCode::Success,
         msg: String::from(""),
-        data: DataWrapper::HashMapData(new_json_data),
+        data: DataWrapper::CategoryData(new_json_data),
         local: comic_json_cache_path.to_str().unwrap().to_string(),
         author: author,
         comic_name: comic_name.clone(),
@@ -572,7 +813,7 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
         return HandleHtmlRes {
             code: StatusCode::Failed,
             msg: String::from("cache comic json failed!"),
-            data: DataWrapper::HashMapData(HashMap::new()),
+            data: DataWrapper::CategoryData(Vec::new()),
             local: String::from(""),
             author: String::from(""),
             comic_name: comic_name,
@@ -584,25 +825,43 @@ pub async fn handle_comic_html(url: String, author: String, app: &AppHandle) ->
     res
 }
 
-pub async fn handle_current_html(url: String) -> HandleHtmlRes {
+// 每组并发获取 5 个章节页的图片，章节页和漫画页使用同一个 mode，刷新时才能发现章节中新增或修改的图片
+async fn fetch_chapter_pages(
+    hrefs: &[String],
+    mode: CacheMode,
+    mut on_group: impl FnMut(&[HandleHtmlRes]),
+) -> Vec<HandleHtmlRes> {
+    const GROUP_SIZE: usize = 5;
+
+    let mut concurrent_results: Vec<HandleHtmlRes> = Vec::new();
+    for group in hrefs.chunks(GROUP_SIZE) {
+        let group_tasks = group
+            .iter()
+            .map(|current_url| handle_current_html(current_url.clone(), mode));
+        let results: Vec<HandleHtmlRes> = join_all(group_tasks).await;
+        on_group(&results);
+        concurrent_results.extend(results);
+    }
+    concurrent_results
+}
+
+pub async fn handle_current_html(url: String, mode: CacheMode) -> HandleHtmlRes {
     // https://www.antbyw.com/plugin.php?id=jameson_manhua&a=read&kuid=169197&zjid=1218556
 
     // 获取current页面zjid
     let zjid = get_url_query(url.clone(), String::from("zjid"));
 
-    // 系统的用户目录
-    let home_dir = home::home_dir().unwrap();
-    let current_html_cache_path = home_dir.join(format!(
-        ".comic_dl_tauri/html_cache/antbyw_current_{}.htmlcache",
-        &zjid
-    ));
-    let current_json_cache_path = home_dir.join(format!(
-        ".comic_dl_tauri/json_cache/antbyw_current_{}.json",
-        &zjid
-    ));
-
-    // 如果已经存在current cache json 直接返回
-    if current_json_cache_path.exists() {
+    let current_html_cache_path = app_paths()
+        .html_cache_dir()
+        .join(format!("antbyw_current_{}.htmlcache", &zjid));
+    let current_json_cache_path = app_paths()
+        .json_cache_dir()
+        .join(format!("antbyw_current_{}.json", &zjid));
+
+    let mut mode = mode;
+    // 如果已经存在current cache json 直接返回，刷新时忽略 json 缓存
+    if mode == CacheMode::Prefer && current_json_cache_path.exists() {
+        touch_cache(&current_json_cache_path);
         match read_from_json::<HandleHtmlRes>(current_json_cache_path.to_str().unwrap()) {
             Ok(res) => {
                 if res.done {
@@ -615,6 +874,8 @@ pub async fn handle_current_html(url: String) -> HandleHtmlRes {
                             res.current_count
                         );
                     }
+                    // 图片数量不对时缓存的页面可能不完整，重新验证后再解析
+                    mode = CacheMode::Revalidate;
                 }
             }
             Err(_e) => {
@@ -627,39 +888,22 @@ pub async fn handle_current_html(url: String) -> HandleHtmlRes {
     }
 
     // 先读缓存，如果没有再去下载current页html然后缓存到本地
-    let html_content;
-    if current_html_cache_path.exists() {
-        html_content = read_file_to_string(current_html_cache_path.to_str().unwrap()).unwrap();
-    } else {
-        // 请求current页面html
-        match retry_request(&url, 5).await {
-            Ok(s) => {
-                html_content = s;
-            }
-            Err(_) => {
-                return HandleHtmlRes {
-                    code: StatusCode::Failed,
-                    msg: String::from("download current html failed!"),
-                    data: DataWrapper::HashMapData(HashMap::new()),
-                    local: String::from(""),
-                    author: String::from(""),
-                    comic_name: String::from(""),
-                    current_name: String::from(""),
-                    current_count: 0,
-                    done: false,
-                };
-            }
-        };
-
-        // 缓存current页面html
-        if let Err(e) = cache_html(&html_content, current_html_cache_path) {
-            error!(
-                "cache {} failed: {}",
-                format!("antbyw_current_{}.htmlcache", &zjid),
-                e
-            );
+    let html_content = match cached_html(&url, &current_html_cache_path, mode).await {
+        Some(s) => s,
+        None => {
+            return HandleHtmlRes {
+                code: StatusCode::Failed,
+                msg: String::from("download current html failed!"),
+                data: DataWrapper::CategoryData(Vec::new()),
+                local: String::from(""),
+                author: String::from(""),
+                comic_name: String::from(""),
+                current_name: String::from(""),
+                current_count: 0,
+                done: false,
+            };
         }
-    }
+    };
 
     let comic_name;
     let current_name;
@@ -700,6 +944,7 @@ pub async fn handle_current_html(url: String) -> HandleHtmlRes {
             href_vec.push(Img {
                 href: href.to_string(),
                 done: false,
+                alt_href: None,
             });
         }
     }
@@ -743,3 +988,103 @@ pub async fn handle_current_html(url: String) -> HandleHtmlRes {
 
     res
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    fn current_page(imgs: &[&str]) -> String {
+        let imgs = imgs
+            .iter()
+            .map(|x| format!(r#"<img data-src="{}">"#, x))
+            .collect::<String>();
+        format!(
+            r#"<ul class="uk-breadcrumb pl0"><li><a>comic</a></li><li><span>第1话</span></li></ul>
+            <span class="uk-badge ml8">{}P</span><div class="uk-zjimg">{}</div>"#,
+            imgs.matches("<img").count(),
+            imgs
+        )
+    }
+
+    // 本地 http 服务，第一次返回一张图片，之后返回两张图片，记录每次请求的请求头
+    fn serve_chapter() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buf[..n]);
+                }
+                let count = {
+                    let mut requests = recorded.lock().unwrap();
+                    requests.push(String::from_utf8_lossy(&head).to_lowercase());
+                    requests.len()
+                };
+                let (etag, body) = if count == 1 {
+                    ("\"v1\"", current_page(&["a.jpg"]))
+                } else {
+                    ("\"v2\"", current_page(&["a.jpg", "b.jpg"]))
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    etag,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (
+            format!("http://127.0.0.1:{}/plugin.php?zjid=test_revalidate", port),
+            requests,
+        )
+    }
+
+    fn img_count(res: &HandleHtmlRes) -> usize {
+        match &res.data {
+            DataWrapper::VecData(imgs) => imgs.len(),
+            _ => 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn revalidate_refetches_chapter_pages() {
+        let cache_dir = std::env::temp_dir().join(format!("comic_dl_test_{}", std::process::id()));
+        std::env::set_var("COMIC_DL_CACHE_DIR", &cache_dir);
+        for dir in [app_paths().html_cache_dir(), app_paths().json_cache_dir()] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let (url, requests) = serve_chapter();
+        let hrefs = vec![url];
+
+        let res = fetch_chapter_pages(&hrefs, CacheMode::Bypass, |_| {}).await;
+        assert_eq!(img_count(&res[0]), 1);
+        // 有缓存时不会请求章节页
+        let res = fetch_chapter_pages(&hrefs, CacheMode::Prefer, |_| {}).await;
+        assert_eq!(img_count(&res[0]), 1);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // 重新验证时带上缓存的 ETag 请求章节页，得到新增的图片
+        let mut groups = 0;
+        let res = fetch_chapter_pages(&hrefs, CacheMode::Revalidate, |_| groups += 1).await;
+        assert_eq!(groups, 1);
+        assert_eq!(img_count(&res[0]), 2);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::db::{check_db_file, get_db_path, DB_CONNECTION};
+use crate::paths::app_paths;
+use crate::utils::read_from_json;
+use crate::{Setting, SETTING};
+use chrono::Local;
+use libsqlite3_sys as ffi;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::ffi::{CStr, CString};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+use std::time::Duration;
+use zip::result::ZipResult;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+// 备份文件的格式版本，恢复时拒绝更新版本的备份
+const BACKUP_VERSION: u32 = 1;
+const MANIFEST_ENTRY: &str = "manifest.json";
+const DB_ENTRY: &str = "db/db.sqlite";
+const SETTING_ENTRY: &str = "setting.json";
+const CACHE_DIRS: [&str; 2] = ["json_cache", "html_cache"];
+// 自动备份的文件名前缀，只有这些文件会按保留份数删除
+const AUTO_PREFIX: &str = "auto_";
+
+// 同一时间只进行一个备份或恢复
+static BACKUP_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    app_version: String,
+    db_version: i32,
+    created_at: String,
+}
+
+// 备份的结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackupInfo {
+    pub path: String,
+    pub size: u64,
+    pub db_version: i32,
+    pub created_at: String,
+}
+
+pub fn default_backup_name() -> String {
+    format!("comic_dl_{}.zip", Local::now().format("%Y%m%d_%H%M%S"))
+}
+
+// 直接打开的 SQLite 连接，diesel 没有提供在线备份接口
+struct RawDb(*mut ffi::sqlite3);
+
+impl RawDb {
+    fn open(path: &Path, flags: i32) -> Result<Self, String> {
+        let c_path = CString::new(path.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+        let mut db = ptr::null_mut();
+        let rc = unsafe { ffi::sqlite3_open_v2(c_path.as_ptr(), &mut db, flags, ptr::null()) };
+        let raw = RawDb(db);
+        if rc != ffi::SQLITE_OK {
+            return Err(format!("open {} failed: {}", path.display(), raw.errmsg()));
+        }
+        Ok(raw)
+    }
+
+    fn errmsg(&self) -> String {
+        unsafe {
+            CStr::from_ptr(ffi::sqlite3_errmsg(self.0))
+                .to_string_lossy()
+                .to_string()
+        }
+    }
+}
+
+impl Drop for RawDb {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sqlite3_close(self.0);
+        }
+    }
+}
+
+// 使用 SQLite 的在线备份接口复制数据库，复制时持有应用的数据库连接，保证内容一致
+fn copy_db(src: &Path, dest: &Path) -> Result<(), String> {
+    let _conn = DB_CONNECTION.get().map(|x| x.lock().unwrap());
+    let src_db = RawDb::open(src, ffi::SQLITE_OPEN_READONLY)?;
+    let dest_db = RawDb::open(dest, ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE)?;
+    let main = CString::new("main").unwrap();
+    unsafe {
+        let backup = ffi::sqlite3_backup_init(dest_db.0, main.as_ptr(), src_db.0, main.as_ptr());
+        if backup.is_null() {
+            return Err(format!("backup init failed: {}", dest_db.errmsg()));
+        }
+        // 被其他进程锁住时最多等待 5 秒
+        let mut busy_count = 0;
+        loop {
+            match ffi::sqlite3_backup_step(backup, 100) {
+                ffi::SQLITE_OK => {}
+                ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED if busy_count < 100 => {
+                    busy_count += 1;
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                _ => break,
+            }
+        }
+        if ffi::sqlite3_backup_finish(backup) != ffi::SQLITE_OK {
+            return Err(format!("backup failed: {}", dest_db.errmsg()));
+        }
+    }
+    Ok(())
+}
+
+fn add_file(zip: &mut ZipWriter<File>, path: &Path, name: &str) -> ZipResult<()> {
+    zip.start_file(name, SimpleFileOptions::default())?;
+    io::copy(&mut File::open(path)?, zip)?;
+    Ok(())
+}
+
+fn add_dir(zip: &mut ZipWriter<File>, dir: &Path, name: &str) -> ZipResult<()> {
+    zip.add_directory(name, SimpleFileOptions::default())?;
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+        if path.is_dir() {
+            add_dir(zip, &path, &entry_name)?;
+        } else {
+            add_file(zip, &path, &entry_name)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_archive(path: &Path, db_path: &Path, manifest: &Manifest) -> ZipResult<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    zip.start_file(MANIFEST_ENTRY, SimpleFileOptions::default())?;
+    serde_json::to_writer_pretty(&mut zip, manifest).map_err(io::Error::from)?;
+    add_file(&mut zip, db_path, DB_ENTRY)?;
+    let setting_path = app_paths().setting_file();
+    if setting_path.exists() {
+        add_file(&mut zip, &setting_path, SETTING_ENTRY)?;
+    }
+    for dir in CACHE_DIRS {
+        let cache_dir = app_paths().cache_dir.join(dir);
+        if cache_dir.is_dir() {
+            add_dir(&mut zip, &cache_dir, dir)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+// 先把数据库复制到临时文件并检查，再和设置、缓存一起写入 zip，完成后改名，中途失败不会留下不完整的备份
+fn create_backup(path: &Path) -> Result<BackupInfo, String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let tmp_db = path.with_extension("sqlite.tmp");
+    let tmp_zip = path.with_extension("zip.tmp");
+    let res = (|| {
+        copy_db(Path::new(&get_db_path()), &tmp_db)?;
+        let manifest = Manifest {
+            version: BACKUP_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            db_version: check_db_file(&tmp_db)?,
+            created_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        };
+        write_archive(&tmp_zip, &tmp_db, &manifest).map_err(|e| e.to_string())?;
+        fs::rename(&tmp_zip, path).map_err(|e| e.to_string())?;
+        Ok(BackupInfo {
+            path: path.to_string_lossy().to_string(),
+            size: fs::metadata(path).map(|x| x.len()).unwrap_or(0),
+            db_version: manifest.db_version,
+            created_at: manifest.created_at,
+        })
+    })();
+    let _ = fs::remove_file(&tmp_db);
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_zip);
+    }
+    res
+}
+
+pub fn backup_to(path: &Path) -> Result<BackupInfo, String> {
+    let _lock = BACKUP_LOCK.lock().unwrap();
+    let info = create_backup(path)?;
+    info!("backup_to: {:?}", &info);
+    Ok(info)
+}
+
+// 检查备份文件并解压到临时目录，只解压已知的文件
+fn stage_backup(path: &Path, staging: &Path) -> Result<Manifest, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut zip = ZipArchive::new(file).map_err(|e| format!("invalid backup file: {}", e))?;
+    let manifest: Manifest = {
+        let entry = zip
+            .by_name(MANIFEST_ENTRY)
+            .map_err(|_| String::from("invalid backup file: manifest.json not found"))?;
+        serde_json::from_reader(entry).map_err(|e| format!("invalid manifest.json: {}", e))?
+    };
+    if manifest.version > BACKUP_VERSION {
+        return Err(format!(
+            "backup version {} is newer than this app",
+            manifest.version
+        ));
+    }
+
+    let _ = fs::remove_dir_all(staging);
+    fs::create_dir_all(staging).map_err(|e| e.to_string())?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| e.to_string())?;
+        let name = match entry.enclosed_name() {
+            Some(name) => name,
+            None => return Err(format!("invalid backup entry: {}", entry.name())),
+        };
+        let known = name == Path::new(DB_ENTRY)
+            || name == Path::new(SETTING_ENTRY)
+            || CACHE_DIRS.iter().any(|dir| name.starts_with(dir));
+        if !known {
+            continue;
+        }
+        let out = staging.join(&name);
+        if entry.is_dir() {
+            fs::create_dir_all(&out).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut out_file = File::create(&out).map_err(|e| e.to_string())?;
+        io::copy(&mut entry, &mut out_file).map_err(|e| e.to_string())?;
+    }
+
+    check_db_file(&staging.join(DB_ENTRY))?;
+    let setting_path = staging.join(SETTING_ENTRY);
+    if setting_path.exists() {
+        read_from_json::<Setting>(setting_path.to_str().unwrap_or(""))
+            .map_err(|e| format!("invalid setting.json: {}", e))?;
+    }
+    Ok(manifest)
+}
+
+// 检查通过后先备份当前的数据，再替换数据库、设置和缓存
+// 数据库通过在线备份接口写入，应用的连接不需要重新打开，之后由调用方执行数据库升级并重新读取任务和设置
+pub fn restore_from(path: &Path) -> Result<(), String> {
+    let _lock = BACKUP_LOCK.lock().unwrap();
+    // 临时目录和缓存在同一个磁盘，解压的缓存可以直接改名
+    let staging = app_paths().cache_dir.join("restore_tmp");
+    let res = stage_backup(path, &staging).and_then(|manifest| {
+        info!("restore_from: {} manifest: {:?}", path.display(), &manifest);
+        let before = app_paths().backup_dir().join(format!(
+            "before_restore_{}.zip",
+            Local::now().format("%Y%m%d_%H%M%S")
+        ));
+        create_backup(&before)?;
+        copy_db(&staging.join(DB_ENTRY), Path::new(&get_db_path()))?;
+        for dir in CACHE_DIRS {
+            let target = app_paths().cache_dir.join(dir);
+            if target.exists() {
+                fs::remove_dir_all(&target).map_err(|e| e.to_string())?;
+            }
+            let staged = staging.join(dir);
+            if staged.exists() {
+                fs::rename(&staged, &target).map_err(|e| e.to_string())?;
+            } else {
+                fs::create_dir_all(&target).map_err(|e| e.to_string())?;
+            }
+        }
+        let staged_setting = staging.join(SETTING_ENTRY);
+        if staged_setting.exists() {
+            fs::copy(&staged_setting, app_paths().setting_file()).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    });
+    let _ = fs::remove_dir_all(&staging);
+    res
+}
+
+// 自动备份按时间排序
+fn auto_backups() -> Vec<PathBuf> {
+    let mut backups: Vec<PathBuf> = match fs::read_dir(app_paths().backup_dir()) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|x| x.path())
+            .filter(|x| {
+                x.file_name()
+                    .and_then(|x| x.to_str())
+                    .is_some_and(|x| x.starts_with(AUTO_PREFIX) && x.ends_with(".zip"))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    backups.sort();
+    backups
+}
+
+// 距离上次自动备份超过设置的间隔时备份，并删除超出保留份数的旧备份
+fn run_auto_backup() {
+    let (interval, keep) = {
+        let setting = SETTING.read().unwrap();
+        (
+            setting.backup_interval.parse::<u64>().unwrap_or(0),
+            setting.backup_keep.parse::<usize>().unwrap_or(7).max(1),
+        )
+    };
+    if interval == 0 {
+        return;
+    }
+    let due = match auto_backups().last() {
+        Some(last) => fs::metadata(last)
+            .and_then(|x| x.modified())
+            .map(|x| x.elapsed().unwrap_or_default() >= Duration::from_secs(interval * 3600))
+            .unwrap_or(true),
+        None => true,
+    };
+    if !due {
+        return;
+    }
+    let path = app_paths().backup_dir().join(format!(
+        "{}{}.zip",
+        AUTO_PREFIX,
+        Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    if let Err(e) = backup_to(&path) {
+        error!("auto backup failed: {}", e);
+        return;
+    }
+    let backups = auto_backups();
+    if backups.len() > keep {
+        for old in backups.iter().take(backups.len() - keep) {
+            info!("remove old backup: {}", old.display());
+            if let Err(e) = fs::remove_file(old) {
+                error!("remove old backup failed: {} e: {}", old.display(), e);
+            }
+        }
+    }
+}
+
+// 每 10 分钟检查一次是否需要自动备份
+pub fn init_backup_scheduler() {
+    tauri::async_runtime::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            if let Err(e) = tauri::async_runtime::spawn_blocking(run_auto_backup).await {
+                error!("run_auto_backup failed: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,51 @@
+use log::info;
+use std::sync::{LazyLock, Mutex};
+use tokio::time::{sleep, Duration, Instant};
+
+// 所有图片下载共用的带宽限制（令牌桶），rate 为 0 时不限制
+struct Bucket {
+    // 每秒字节数
+    rate: u64,
+    available: f64,
+    last: Instant,
+}
+
+static BANDWIDTH: LazyLock<Mutex<Bucket>> = LazyLock::new(|| {
+    Mutex::new(Bucket {
+        rate: 0,
+        available: 0.0,
+        last: Instant::now(),
+    })
+});
+
+pub fn set_bandwidth_limit(bytes_per_sec: u64) {
+    let mut bucket = BANDWIDTH.lock().unwrap();
+    if bucket.rate != bytes_per_sec {
+        info!("set_bandwidth_limit: {} B/s", bytes_per_sec);
+        bucket.rate = bytes_per_sec;
+        bucket.available = bytes_per_sec as f64;
+        bucket.last = Instant::now();
+    }
+}
+
+// 读取 bytes 字节后调用，超出限制时等待
+pub async fn consume_bandwidth(bytes: usize) {
+    let wait = {
+        let mut bucket = BANDWIDTH.lock().unwrap();
+        if bucket.rate == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let rate = bucket.rate as f64;
+        // 最多积攒 1 秒的流量
+        bucket.available =
+            (bucket.available + now.duration_since(bucket.last).as_secs_f64() * rate).min(rate);
+        bucket.last = now;
+        bucket.available -= bytes as f64;
+        if bucket.available >= 0.0 {
+            return;
+        }
+        Duration::from_secs_f64(-bucket.available / rate)
+    };
+    sleep(wait).await;
+}
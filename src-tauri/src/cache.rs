@@ -0,0 +1,221 @@
+use crate::antbyw::validators_path;
+use crate::models::PartialDownloadTask;
+use crate::paths::app_paths;
+use crate::SETTING;
+use log::{error, info};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// 缓存文件名 antbyw_{category}_{key}.htmlcache(.meta) / antbyw_{category}_{key}.json
+const CATEGORIES: [&str; 3] = ["author", "comic", "current"];
+
+struct CacheFile {
+    path: PathBuf,
+    kind: &'static str,
+    category: String,
+    size: u64,
+    // 读取缓存时会更新修改时间，作为最近使用的时间
+    modified: SystemTime,
+}
+
+// 一类缓存的文件数和大小
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStat {
+    pub category: String,
+    pub kind: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub files: usize,
+    pub bytes: u64,
+    // 设置的上限，0 不限制
+    pub max_bytes: u64,
+    pub categories: Vec<CacheStat>,
+}
+
+// 一次清理的结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PruneResult {
+    // 超过设置的天数没有使用
+    pub expired: usize,
+    // 超出大小上限，按最近使用时间删除
+    pub evicted: usize,
+    pub bytes: u64,
+}
+
+fn category_of(name: &str) -> String {
+    name.strip_prefix("antbyw_")
+        .and_then(|x| x.split('_').next())
+        .filter(|x| CATEGORIES.contains(x))
+        .unwrap_or("other")
+        .to_string()
+}
+
+fn list_cache_files() -> Vec<CacheFile> {
+    let paths = app_paths();
+    let mut files = Vec::new();
+    for (kind, dir) in [
+        ("html", paths.html_cache_dir()),
+        ("json", paths.json_cache_dir()),
+    ] {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!(
+                    "list_cache_files read_dir failed: {} e: {}",
+                    dir.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            files.push(CacheFile {
+                path: entry.path(),
+                kind,
+                category: category_of(&entry.file_name().to_string_lossy()),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+    }
+    files
+}
+
+fn remove_cache_file(path: &Path) -> bool {
+    match fs::remove_file(path) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("remove cache failed: {} e: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+// 读取缓存时更新修改时间，清理时按最近使用的时间淘汰
+pub fn touch_cache(path: &Path) {
+    if let Ok(file) = File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+// 设置的缓存大小上限（字节）和过期时间，0 不限制
+fn cache_limits() -> (u64, Option<Duration>) {
+    let setting = SETTING.read().unwrap();
+    let max_mb = setting.cache_max_size.parse::<u64>().unwrap_or(500);
+    let ttl_days = setting.cache_ttl.parse::<u64>().unwrap_or(30);
+    (
+        max_mb * 1024 * 1024,
+        (ttl_days > 0).then(|| Duration::from_secs(ttl_days * 24 * 3600)),
+    )
+}
+
+pub fn cache_stats() -> CacheStats {
+    let mut categories: BTreeMap<(String, &'static str), CacheStat> = BTreeMap::new();
+    let mut stats = CacheStats {
+        max_bytes: cache_limits().0,
+        ..Default::default()
+    };
+    for file in list_cache_files() {
+        stats.files += 1;
+        stats.bytes += file.size;
+        let stat = categories
+            .entry((file.category.clone(), file.kind))
+            .or_insert_with(|| CacheStat {
+                category: file.category.clone(),
+                kind: file.kind.to_string(),
+                ..Default::default()
+            });
+        stat.files += 1;
+        stat.bytes += file.size;
+    }
+    stats.categories = categories.into_values().collect();
+    stats
+}
+
+// 先删除超过设置天数没有使用的缓存，再按最近使用的时间从旧到新删除，直到不超过大小上限
+pub fn prune_cache() -> PruneResult {
+    let (max_bytes, ttl) = cache_limits();
+    let now = SystemTime::now();
+    let mut result = PruneResult::default();
+    let mut kept = Vec::new();
+    for file in list_cache_files() {
+        let expired =
+            ttl.is_some_and(|ttl| now.duration_since(file.modified).unwrap_or_default() > ttl);
+        if expired && remove_cache_file(&file.path) {
+            result.expired += 1;
+            result.bytes += file.size;
+        } else {
+            kept.push(file);
+        }
+    }
+    if max_bytes > 0 {
+        let mut total: u64 = kept.iter().map(|x| x.size).sum();
+        kept.sort_by_key(|x| x.modified);
+        for file in kept.iter() {
+            if total <= max_bytes {
+                break;
+            }
+            if remove_cache_file(&file.path) {
+                total -= file.size;
+                result.evicted += 1;
+                result.bytes += file.size;
+            }
+        }
+    }
+    if result.expired > 0 || result.evicted > 0 {
+        info!("prune_cache: {:?}", &result);
+    }
+    result
+}
+
+// 任务的 json 缓存保存在 local_path，html 缓存和它的校验信息同名
+fn task_cache_files(task: &PartialDownloadTask) -> Vec<PathBuf> {
+    let stem = match Path::new(&task.local_path)
+        .file_stem()
+        .and_then(|x| x.to_str())
+    {
+        Some(stem) if stem.starts_with("antbyw_") => stem,
+        _ => return Vec::new(),
+    };
+    let paths = app_paths();
+    let html_cache_path = paths.html_cache_dir().join(format!("{}.htmlcache", stem));
+    vec![
+        paths.json_cache_dir().join(format!("{}.json", stem)),
+        validators_path(&html_cache_path),
+        html_cache_path,
+    ]
+}
+
+// 删除任务后清理它的缓存，同一部漫画的其他任务（单行本 单话 番外篇）还在时保留
+pub fn clear_task_cache(deleted: &[PartialDownloadTask], remaining: &[PartialDownloadTask]) {
+    let in_use: HashSet<PathBuf> = remaining.iter().flat_map(task_cache_files).collect();
+    for path in deleted.iter().flat_map(task_cache_files) {
+        if !in_use.contains(&path) && path.exists() && remove_cache_file(&path) {
+            info!("clear task cache: {}", path.display());
+        }
+    }
+}
+
+// 启动时和之后每小时清理一次缓存
+pub fn init_cache_manager() {
+    tauri::async_runtime::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            if let Err(e) = tauri::async_runtime::spawn_blocking(prune_cache).await {
+                error!("prune_cache failed: {}", e);
+            }
+        }
+    });
+}
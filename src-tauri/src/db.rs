@@ -1,9 +1,13 @@
-use crate::utils::ErrorMsg;
+use crate::antbyw::{CurrentElement, Img};
+use crate::paths::app_paths;
+use crate::utils::{get_second_level_domain, ErrorMsg};
 use crate::{models::*, StartAllData};
 use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use log::{error, info};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::sync::{Mutex, OnceLock};
@@ -64,7 +68,298 @@ pub fn create_table() -> QueryResult<()> {
             done BOOLEAN NOT NULL
         );
     "#;
-    conn.batch_execute(sql)
+    conn.batch_execute(sql)?;
+    migrate_table(&mut conn)
+}
+
+#[derive(QueryableByName)]
+struct AveragePageBytes {
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::BigInt>)]
+    bytes: Option<i64>,
+}
+
+#[derive(QueryableByName)]
+struct UserVersion {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    user_version: i32,
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheck {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    integrity_check: String,
+}
+
+// 数据库结构升级，按顺序执行，执行过的版本号记录在 PRAGMA user_version
+const MIGRATIONS: [&str; 8] = [
+    // 非 单行本 单话 番外篇 的类别保存原始标题
+    "ALTER TABLE download_tasks ADD COLUMN category TEXT NOT NULL DEFAULT '';",
+    // 作者表，作者页添加的任务通过 author_id 关联
+    r#"
+        CREATE TABLE IF NOT EXISTS authors (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            comic_count INTEGER NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+        ALTER TABLE download_tasks ADD COLUMN author_id INTEGER NOT NULL DEFAULT 0;
+    "#,
+    // 漫画 章节 图片 分表保存，替代 download_tasks.cache_json
+    r#"
+        CREATE TABLE IF NOT EXISTS series (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            url TEXT NOT NULL,
+            comic_name TEXT NOT NULL,
+            author TEXT NOT NULL,
+            author_id INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS chapters (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            series_id INTEGER NOT NULL,
+            task_id INTEGER NOT NULL,
+            group_index INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            href TEXT NOT NULL,
+            count INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS pages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            chapter_id INTEGER NOT NULL,
+            task_id INTEGER NOT NULL,
+            group_index INTEGER NOT NULL,
+            page_index INTEGER NOT NULL,
+            href TEXT NOT NULL,
+            alt_href TEXT,
+            done BOOLEAN NOT NULL,
+            error_msg TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_series_url ON series (url);
+        CREATE INDEX IF NOT EXISTS idx_chapters_task ON chapters (task_id, group_index);
+        CREATE INDEX IF NOT EXISTS idx_pages_task ON pages (task_id, group_index, page_index);
+    "#,
+    // 任务分配图片下载位置的权重
+    "ALTER TABLE download_tasks ADD COLUMN weight INTEGER NOT NULL DEFAULT 1;",
+    // 任务在队列中的顺序，已有任务按添加顺序
+    r#"
+        ALTER TABLE download_tasks ADD COLUMN queue_position INTEGER NOT NULL DEFAULT 0;
+        UPDATE download_tasks SET queue_position = id;
+    "#,
+    // 任务被暂停的原因，schedule 表示不在下载时间段内
+    "ALTER TABLE download_tasks ADD COLUMN pause_reason TEXT NOT NULL DEFAULT '';",
+    // 下载历史，只追加不修改，删除任务后仍然保留
+    r#"
+        CREATE TABLE IF NOT EXISTS task_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            task_id INTEGER NOT NULL,
+            event TEXT NOT NULL,
+            comic_name TEXT NOT NULL,
+            author TEXT NOT NULL,
+            dl_type TEXT NOT NULL,
+            site TEXT NOT NULL,
+            bytes BIGINT NOT NULL DEFAULT 0,
+            duration_ms BIGINT NOT NULL DEFAULT 0,
+            img_count INTEGER NOT NULL DEFAULT 0,
+            error_count INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS host_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            task_id INTEGER NOT NULL,
+            host TEXT NOT NULL,
+            requests INTEGER NOT NULL,
+            failures INTEGER NOT NULL,
+            bytes BIGINT NOT NULL,
+            duration_ms BIGINT NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_task_history_created ON task_history (created_at);
+        CREATE INDEX IF NOT EXISTS idx_host_history_host ON host_history (host);
+    "#,
+    // 图片保存的路径，为空时为旧版本按固定目录结构保存的图片
+    "ALTER TABLE pages ADD COLUMN file_path TEXT NOT NULL DEFAULT '';",
+];
+
+// 检查备份中的数据库是否完整，并且不是更新版本的结构，返回结构版本
+pub fn check_db_file(path: &Path) -> Result<i32, String> {
+    if !path.is_file() {
+        return Err(String::from("db file not found"));
+    }
+    let mut conn =
+        SqliteConnection::establish(path.to_str().unwrap_or("")).map_err(|e| e.to_string())?;
+    let check = diesel::sql_query("PRAGMA integrity_check")
+        .get_result::<IntegrityCheck>(&mut conn)
+        .map_err(|e| e.to_string())?;
+    if check.integrity_check != "ok" {
+        return Err(format!(
+            "db integrity check failed: {}",
+            check.integrity_check
+        ));
+    }
+    let version = diesel::sql_query("PRAGMA user_version")
+        .get_result::<UserVersion>(&mut conn)
+        .map_err(|e| e.to_string())?
+        .user_version;
+    if version as usize > MIGRATIONS.len() {
+        return Err(format!("db version {} is newer than this app", version));
+    }
+    diesel::sql_query("SELECT id FROM download_tasks LIMIT 1")
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(version)
+}
+
+fn migrate_table(conn: &mut SqliteConnection) -> QueryResult<()> {
+    let version = diesel::sql_query("PRAGMA user_version")
+        .get_result::<UserVersion>(conn)?
+        .user_version;
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("migrate_table version: {}", i + 1);
+        // 每一步和版本号在同一个事务中，失败时整步回滚，下次启动重新执行
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            conn.batch_execute(sql)?;
+            conn.batch_execute(&format!("PRAGMA user_version = {};", i + 1))
+        })?;
+    }
+    convert_cache_json(conn)
+}
+
+// 旧版本 error_vec 中记录的下载失败的图片
+#[derive(Deserialize)]
+struct OldFailedImg {
+    group_index: usize,
+    index: usize,
+    error_msg: String,
+}
+
+// 把旧版本 download_tasks.cache_json 中的数据转换到 series chapters pages 表，转换后清空 cache_json
+// error_vec 中的失败图片写入 pages.error_msg，重试时只下载这些图片
+fn convert_cache_json(conn: &mut SqliteConnection) -> QueryResult<()> {
+    use crate::schema::download_tasks::dsl::*;
+
+    let old_tasks = download_tasks
+        .filter(cache_json.ne(""))
+        .load::<DownloadTask>(conn)?;
+    for task in old_tasks.iter() {
+        let groups: Vec<CurrentElement> = if task.dl_type == "current" {
+            match serde_json::from_str::<Vec<Img>>(&task.cache_json) {
+                Ok(imgs) => vec![CurrentElement {
+                    name: task.comic_name.clone(),
+                    href: task.url.clone(),
+                    count: imgs.len(),
+                    imgs,
+                    done: false,
+                }],
+                Err(e) => {
+                    error!("convert_cache_json parse failed: {} e: {}", task.id, e);
+                    continue;
+                }
+            }
+        } else {
+            match serde_json::from_str::<Vec<CurrentElement>>(&task.cache_json) {
+                Ok(groups) => groups,
+                Err(e) => {
+                    error!("convert_cache_json parse failed: {} e: {}", task.id, e);
+                    continue;
+                }
+            }
+        };
+
+        // 更早的版本只记录了错误信息，没有图片位置
+        let failed: Vec<OldFailedImg> = serde_json::from_str(&task.error_vec).unwrap_or_default();
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            insert_task_pages(conn, task, &groups)?;
+            for img in failed.iter() {
+                use crate::schema::pages;
+                diesel::update(
+                    pages::table
+                        .filter(pages::task_id.eq(task.id))
+                        .filter(pages::group_index.eq(img.group_index as i32))
+                        .filter(pages::page_index.eq(img.index as i32))
+                        .filter(pages::done.eq(false)),
+                )
+                .set(pages::error_msg.eq(&img.error_msg))
+                .execute(conn)?;
+            }
+            diesel::update(download_tasks.find(task.id))
+                .set(cache_json.eq(""))
+                .execute(conn)?;
+            Ok(())
+        })?;
+        info!("convert_cache_json task: {}", task.id);
+    }
+    Ok(())
+}
+
+// 写入任务的漫画 章节 图片数据，同一个 url 的任务共用一条 series
+fn insert_task_pages(
+    conn: &mut SqliteConnection,
+    task: &DownloadTask,
+    groups: &Vec<CurrentElement>,
+) -> QueryResult<()> {
+    use crate::schema::{chapters, pages, series};
+
+    let found = series::table
+        .filter(series::url.eq(&task.url))
+        .select(series::id)
+        .first::<i32>(conn)
+        .optional()?;
+    let series_id = match found {
+        Some(series_id) => series_id,
+        None => diesel::insert_into(series::table)
+            .values(&NewSeries {
+                url: &task.url,
+                comic_name: &task.comic_name,
+                author: &task.author,
+                author_id: task.author_id,
+            })
+            .returning(series::id)
+            .get_result::<i32>(conn)?,
+    };
+
+    for (group_index, group) in groups.iter().enumerate() {
+        let chapter_id = diesel::insert_into(chapters::table)
+            .values(&NewChapter {
+                series_id,
+                task_id: task.id,
+                group_index: group_index as i32,
+                name: &group.name,
+                href: &group.href,
+                count: group.imgs.len() as i32,
+            })
+            .returning(chapters::id)
+            .get_result::<i32>(conn)?;
+        let new_pages: Vec<NewPage> = group
+            .imgs
+            .iter()
+            .enumerate()
+            .map(|(page_index, img)| NewPage {
+                chapter_id,
+                task_id: task.id,
+                group_index: group_index as i32,
+                page_index: page_index as i32,
+                href: &img.href,
+                alt_href: img.alt_href.as_deref(),
+                done: img.done,
+                error_msg: "",
+                file_path: "",
+            })
+            .collect();
+        diesel::insert_into(pages::table)
+            .values(&new_pages)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+// 删除任务的章节和图片，以及没有章节的漫画
+fn delete_task_pages(conn: &mut SqliteConnection, task_ids: &Vec<i32>) -> QueryResult<()> {
+    use crate::schema::{chapters, pages};
+
+    diesel::delete(pages::table.filter(pages::task_id.eq_any(task_ids))).execute(conn)?;
+    diesel::delete(chapters::table.filter(chapters::task_id.eq_any(task_ids))).execute(conn)?;
+    conn.batch_execute("DELETE FROM series WHERE id NOT IN (SELECT series_id FROM chapters);")
 }
 
 // 插入新的下载任务
@@ -72,7 +367,7 @@ pub fn create_download_task(
     _dl_type: &str,
     _status: &str,
     _local_path: &str,
-    _cache_json: &str,
+    _groups: &Vec<CurrentElement>,
     _url: &str,
     _author: &str,
     _comic_name: &str,
@@ -81,15 +376,21 @@ pub fn create_download_task(
     _now_count: i32,
     _error_vec: &str,
     _done: bool,
+    _category: &str,
+    _author_id: i32,
 ) -> QueryResult<DownloadTask> {
     use crate::schema::download_tasks::dsl::*;
     let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
 
+    // 新任务排在队列最后
+    let max_position: Option<i32> = download_tasks
+        .select(diesel::dsl::max(queue_position))
+        .first(&mut *conn)?;
     let new_task = NewDownloadTask {
         dl_type: _dl_type,
         status: _status,
         local_path: _local_path,
-        cache_json: _cache_json,
+        cache_json: "",
         url: _url,
         author: _author,
         comic_name: _comic_name,
@@ -98,25 +399,54 @@ pub fn create_download_task(
         now_count: _now_count,
         error_vec: _error_vec,
         done: _done,
+        category: _category,
+        author_id: _author_id,
+        queue_position: max_position.unwrap_or(0) + 1,
     };
 
-    let row_id = diesel::insert_into(download_tasks)
-        .values(&new_task)
-        .returning(id)
-        .get_result::<i32>(&mut *conn)?;
-    info!("create_download_task row_id: {:?}", row_id);
+    conn.transaction(|conn| {
+        let row_id = diesel::insert_into(download_tasks)
+            .values(&new_task)
+            .returning(id)
+            .get_result::<i32>(conn)?;
+        info!("create_download_task row_id: {:?}", row_id);
+
+        // 查询最新插入的记录
+        let task: DownloadTask = download_tasks.find(row_id).first(conn)?;
+        insert_task_pages(conn, &task, _groups)?;
 
-    // 查询最新插入的记录
-    download_tasks.find(row_id).first(&mut *conn)
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let site = get_second_level_domain(_url).unwrap_or_default();
+        diesel::insert_into(crate::schema::task_history::table)
+            .values(&NewTaskHistory {
+                task_id: row_id,
+                event: "added",
+                comic_name: _comic_name,
+                author: _author,
+                dl_type: _dl_type,
+                site: &site,
+                bytes: 0,
+                duration_ms: 0,
+                img_count: 0,
+                error_count: 0,
+                created_at: &now,
+            })
+            .execute(conn)?;
+        Ok(task)
+    })
 }
 
 // 更新下载任务
-pub fn update_download_task_status(task_id: i32, status_temp: &str) -> QueryResult<usize> {
+pub fn update_download_task_status_reason(
+    task_id: i32,
+    status_temp: &str,
+    reason_temp: &str,
+) -> QueryResult<usize> {
     use crate::schema::download_tasks::dsl::*;
     let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
 
     diesel::update(download_tasks.find(task_id))
-        .set(status.eq(status_temp))
+        .set((status.eq(status_temp), pause_reason.eq(reason_temp)))
         .execute(&mut *conn)
 }
 
@@ -124,17 +454,12 @@ pub fn update_download_task_progress(
     task_id: i32,
     _progress: &str,
     _now_count: i32,
-    _cache_json: &str,
 ) -> QueryResult<usize> {
     use crate::schema::download_tasks::dsl::*;
     let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
 
     diesel::update(download_tasks.find(task_id))
-        .set((
-            progress.eq(_progress),
-            now_count.eq(_now_count),
-            cache_json.eq(_cache_json),
-        ))
+        .set((progress.eq(_progress), now_count.eq(_now_count)))
         .execute(&mut *conn)
 }
 #[allow(dead_code)]
@@ -154,7 +479,6 @@ pub fn update_download_task_progress_error(
     task_id: i32,
     _progress: &str,
     _now_count: i32,
-    _cache_json: &str,
     _error_vec: &str,
     _status: &str,
 ) -> QueryResult<usize> {
@@ -165,7 +489,6 @@ pub fn update_download_task_progress_error(
         .set((
             progress.eq(_progress),
             now_count.eq(_now_count),
-            cache_json.eq(_cache_json),
             error_vec.eq(_error_vec),
             status.eq(_status),
         ))
@@ -177,9 +500,13 @@ pub fn delete_download_task(task_id: i32) -> QueryResult<usize> {
     use crate::schema::download_tasks::dsl::*;
     let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
 
-    let row_id = diesel::delete(download_tasks.find(task_id))
-        .returning(id)
-        .get_result::<i32>(&mut *conn)?;
+    let row_id = conn.transaction(|conn| {
+        let row_id = diesel::delete(download_tasks.find(task_id))
+            .returning(id)
+            .get_result::<i32>(conn)?;
+        delete_task_pages(conn, &vec![row_id])?;
+        Ok::<i32, diesel::result::Error>(row_id)
+    })?;
 
     info!("delete_download_task row_id: {}", row_id);
 
@@ -201,8 +528,23 @@ pub fn get_all_download_tasks() -> QueryResult<Vec<PartialDownloadTask>> {
 
     download_tasks
         .select((
-            id, dl_type, status, local_path, url, author, comic_name, progress, count, now_count,
-            error_vec, done,
+            id,
+            dl_type,
+            status,
+            local_path,
+            url,
+            author,
+            comic_name,
+            progress,
+            count,
+            now_count,
+            error_vec,
+            done,
+            category,
+            author_id,
+            weight,
+            queue_position,
+            pause_reason,
         ))
         .load::<PartialDownloadTask>(&mut *conn)
     // download_tasks.load::<DownloadTask>(&mut *conn)
@@ -222,22 +564,577 @@ pub fn find_tasks_by_dl_type_and_url(
         .load::<DownloadTask>(&mut *conn)
 }
 
+pub fn update_download_task_weight(task_id: i32, weight_temp: i32) -> QueryResult<usize> {
+    use crate::schema::download_tasks::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    diesel::update(download_tasks.find(task_id))
+        .set(weight.eq(weight_temp))
+        .execute(&mut *conn)
+}
+
+pub fn update_queue_positions(positions: &Vec<(i32, i32)>) -> QueryResult<()> {
+    use crate::schema::download_tasks::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    conn.transaction(|conn| {
+        for (task_id, position) in positions.iter() {
+            diesel::update(download_tasks.find(task_id))
+                .set(queue_position.eq(position))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
 pub fn update_batch_status(tasks: &Vec<StartAllData>) {
     use crate::schema::download_tasks::dsl::*;
     let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
 
+    // 手动修改状态时清除暂停原因
     for i in tasks.iter() {
         let _ = diesel::update(download_tasks.find(i.id))
-            .set(status.eq(i.status.clone()))
+            .set((status.eq(i.status.clone()), pause_reason.eq("")))
             .execute(&mut *conn);
     }
 }
 
+// 启动时处理上次未正常退出时留下的 downloading / waiting 任务：
+// 根据 pages 的 done 重新计算进度并改为 stopped，返回这些任务的 id（downloading 在前）
+// 因为下载时间段或磁盘空间等待的任务保持 waiting 和原因，条件满足后自动继续
+pub fn reset_interrupted_tasks() -> QueryResult<Vec<i32>> {
+    use crate::schema::{download_tasks, pages};
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    conn.transaction(|conn| {
+        let interrupted: Vec<(i32, String, i32, String)> = download_tasks::table
+            .filter(download_tasks::status.eq_any(["downloading", "waiting"]))
+            .select((
+                download_tasks::id,
+                download_tasks::status,
+                download_tasks::count,
+                download_tasks::pause_reason,
+            ))
+            .order(download_tasks::id.asc())
+            .load(conn)?;
+
+        for (task_id, task_status, task_count, reason) in interrupted.iter() {
+            let done_count: i64 = pages::table
+                .filter(pages::task_id.eq(task_id))
+                .filter(pages::done.eq(true))
+                .count()
+                .get_result(conn)?;
+            let task_progress = if *task_count > 0 {
+                format!("{:.2}", (done_count as f32) / (*task_count as f32) * 100.00)
+            } else {
+                String::from("0.00")
+            };
+            let paused = task_status == "waiting" && !reason.is_empty();
+            diesel::update(download_tasks::table.find(task_id))
+                .set((
+                    download_tasks::status.eq(if paused { "waiting" } else { "stopped" }),
+                    download_tasks::pause_reason.eq(if paused { reason.as_str() } else { "" }),
+                    download_tasks::now_count.eq(done_count as i32),
+                    download_tasks::progress.eq(task_progress),
+                ))
+                .execute(conn)?;
+        }
+
+        let (mut ids, waiting_ids): (Vec<_>, Vec<_>) = interrupted
+            .into_iter()
+            .filter(|(_, task_status, _, reason)| task_status == "downloading" || reason.is_empty())
+            .partition(|(_, task_status, _, _)| task_status == "downloading");
+        ids.extend(waiting_ids);
+        Ok(ids.into_iter().map(|(task_id, _, _, _)| task_id).collect())
+    })
+}
+
 pub fn delete_batch_status_not_downloading(ids: Vec<i32>) -> QueryResult<usize> {
     use crate::schema::download_tasks::dsl::*;
     let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
 
-    diesel::delete(download_tasks.filter(id.eq_any(ids))).execute(&mut *conn)
+    conn.transaction(|conn| {
+        let res = diesel::delete(download_tasks.filter(id.eq_any(&ids))).execute(conn)?;
+        delete_task_pages(conn, &ids)?;
+        Ok(res)
+    })
+}
+
+// 读取任务的章节和图片，按 group_index page_index 排序
+pub fn get_task_groups(_task_id: i32) -> QueryResult<Vec<CurrentElement>> {
+    use crate::schema::{chapters, pages};
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let chapter_rows = chapters::table
+        .filter(chapters::task_id.eq(_task_id))
+        .order(chapters::group_index.asc())
+        .load::<Chapter>(&mut *conn)?;
+    let page_rows = pages::table
+        .filter(pages::task_id.eq(_task_id))
+        .order((pages::group_index.asc(), pages::page_index.asc()))
+        .load::<Page>(&mut *conn)?;
+
+    let mut groups: Vec<CurrentElement> = chapter_rows
+        .iter()
+        .map(|x| CurrentElement {
+            name: x.name.clone(),
+            href: x.href.clone(),
+            imgs: Vec::new(),
+            count: x.count as usize,
+            done: false,
+        })
+        .collect();
+    for page in page_rows.into_iter() {
+        if let Some(group) = groups.get_mut(page.group_index as usize) {
+            group.imgs.push(Img {
+                href: page.href,
+                done: page.done,
+                alt_href: page.alt_href,
+            });
+        }
+    }
+    for group in groups.iter_mut() {
+        group.done = group.imgs.iter().all(|x| x.done);
+    }
+    Ok(groups)
+}
+
+// 在章节末尾追加图片，更新章节和任务的图片数，已完成的任务改为 stopped，返回更新后的任务
+pub fn append_task_pages(
+    _task_id: i32,
+    _group_index: i32,
+    hrefs: &Vec<String>,
+) -> QueryResult<DownloadTask> {
+    use crate::schema::{chapters, download_tasks, pages};
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let chapter = chapters::table
+            .filter(chapters::task_id.eq(_task_id))
+            .filter(chapters::group_index.eq(_group_index))
+            .first::<Chapter>(conn)?;
+        let page_count = pages::table
+            .filter(pages::chapter_id.eq(chapter.id))
+            .count()
+            .get_result::<i64>(conn)? as i32;
+        let new_pages: Vec<NewPage> = hrefs
+            .iter()
+            .enumerate()
+            .map(|(i, href)| NewPage {
+                chapter_id: chapter.id,
+                task_id: _task_id,
+                group_index: _group_index,
+                page_index: page_count + i as i32,
+                href,
+                alt_href: None,
+                done: false,
+                error_msg: "",
+                file_path: "",
+            })
+            .collect();
+        diesel::insert_into(pages::table)
+            .values(&new_pages)
+            .execute(conn)?;
+        diesel::update(chapters::table.find(chapter.id))
+            .set(chapters::count.eq(page_count + hrefs.len() as i32))
+            .execute(conn)?;
+
+        let task = download_tasks::table
+            .find(_task_id)
+            .first::<DownloadTask>(conn)?;
+        let new_count = task.count + hrefs.len() as i32;
+        let new_status = if task.status == "finished" {
+            "stopped"
+        } else {
+            task.status.as_str()
+        };
+        diesel::update(download_tasks::table.find(_task_id))
+            .set((
+                download_tasks::count.eq(new_count),
+                download_tasks::progress.eq(format!(
+                    "{:.2}",
+                    (task.now_count as f32) / (new_count as f32) * 100.00
+                )),
+                download_tasks::status.eq(new_status),
+                download_tasks::done.eq(false),
+            ))
+            .execute(conn)?;
+        download_tasks::table
+            .find(_task_id)
+            .first::<DownloadTask>(conn)
+    })
+}
+
+// 保存单张图片的下载结果
+pub fn update_page_result(
+    _task_id: i32,
+    _group_index: i32,
+    _page_index: i32,
+    _done: bool,
+    _error_msg: &str,
+    _file_path: &str,
+) -> QueryResult<usize> {
+    use crate::schema::pages::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    diesel::update(
+        pages
+            .filter(task_id.eq(_task_id))
+            .filter(group_index.eq(_group_index))
+            .filter(page_index.eq(_page_index)),
+    )
+    .set((
+        done.eq(_done),
+        error_msg.eq(_error_msg),
+        file_path.eq(_file_path),
+    ))
+    .execute(&mut *conn)
+}
+
+pub fn update_page_alt_href(
+    _task_id: i32,
+    _group_index: i32,
+    _page_index: i32,
+    _alt_href: &str,
+) -> QueryResult<usize> {
+    use crate::schema::pages::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    diesel::update(
+        pages
+            .filter(task_id.eq(_task_id))
+            .filter(group_index.eq(_group_index))
+            .filter(page_index.eq(_page_index)),
+    )
+    .set(alt_href.eq(Some(_alt_href)))
+    .execute(&mut *conn)
+}
+
+// 查询下载失败且未完成的图片 (group_index, page_index)
+pub fn get_failed_pages(_task_id: i32) -> QueryResult<Vec<(i32, i32)>> {
+    use crate::schema::pages::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    pages
+        .filter(task_id.eq(_task_id))
+        .filter(done.eq(false))
+        .filter(error_msg.ne(""))
+        .select((group_index, page_index))
+        .load::<(i32, i32)>(&mut *conn)
+}
+
+// 查询已下载的图片 (group_index, page_index, 章节名, 保存路径)
+pub fn get_done_page_files(_task_id: i32) -> QueryResult<Vec<(i32, i32, String, String)>> {
+    use crate::schema::{chapters, pages};
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let names: HashMap<i32, String> = chapters::table
+        .filter(chapters::task_id.eq(_task_id))
+        .select((chapters::group_index, chapters::name))
+        .load::<(i32, String)>(&mut *conn)?
+        .into_iter()
+        .collect();
+    let rows = pages::table
+        .filter(pages::task_id.eq(_task_id))
+        .filter(pages::done.eq(true))
+        .order((pages::group_index, pages::page_index))
+        .select((pages::group_index, pages::page_index, pages::file_path))
+        .load::<(i32, i32, String)>(&mut *conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|(_group_index, _page_index, _file_path)| {
+            let name = names.get(&_group_index).cloned().unwrap_or_default();
+            (_group_index, _page_index, name, _file_path)
+        })
+        .collect())
+}
+
+// 任务所有章节 (group_index, 章节名)，按 group_index 排序
+pub fn get_chapter_names(_task_id: i32) -> QueryResult<Vec<(i32, String)>> {
+    use crate::schema::chapters::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    chapters
+        .filter(task_id.eq(_task_id))
+        .order(group_index)
+        .select((group_index, name))
+        .load::<(i32, String)>(&mut *conn)
+}
+
+// 移动图片后更新保存路径 (group_index, page_index, 新路径)
+pub fn update_page_file_paths(_task_id: i32, paths: &Vec<(i32, i32, String)>) -> QueryResult<()> {
+    use crate::schema::pages::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    conn.transaction(|conn| {
+        for (_group_index, _page_index, _file_path) in paths.iter() {
+            diesel::update(
+                pages
+                    .filter(task_id.eq(_task_id))
+                    .filter(group_index.eq(_group_index))
+                    .filter(page_index.eq(_page_index)),
+            )
+            .set(file_path.eq(_file_path))
+            .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+// 查询任务包含的所有章节 href
+pub fn get_chapter_hrefs_by_task_ids(task_ids: Vec<i32>) -> QueryResult<Vec<String>> {
+    use crate::schema::chapters::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    chapters
+        .filter(task_id.eq_any(task_ids))
+        .select(href)
+        .load::<String>(&mut *conn)
+}
+
+// 统计任务每个章节的下载进度
+pub fn get_task_chapter_progress(_task_id: i32) -> QueryResult<Vec<ChapterProgress>> {
+    use crate::schema::{chapters, pages};
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let chapter_rows = chapters::table
+        .filter(chapters::task_id.eq(_task_id))
+        .order(chapters::group_index.asc())
+        .load::<Chapter>(&mut *conn)?;
+    let page_rows = pages::table
+        .filter(pages::task_id.eq(_task_id))
+        .select((pages::group_index, pages::done, pages::error_msg))
+        .load::<(i32, bool, String)>(&mut *conn)?;
+
+    let mut res: Vec<ChapterProgress> = chapter_rows
+        .into_iter()
+        .map(|x| ChapterProgress {
+            group_index: x.group_index,
+            name: x.name,
+            href: x.href,
+            count: x.count,
+            done_count: 0,
+            failed_count: 0,
+        })
+        .collect();
+    for (_group_index, _done, _error_msg) in page_rows.iter() {
+        if let Some(chapter) = res.get_mut(*_group_index as usize) {
+            if *_done {
+                chapter.done_count += 1;
+            } else if !_error_msg.is_empty() {
+                chapter.failed_count += 1;
+            }
+        }
+    }
+    Ok(res)
+}
+
+// 根据作者页 url 查询作者，不存在时创建
+pub fn find_or_create_author(_name: &str, _url: &str, _comic_count: i32) -> QueryResult<Author> {
+    use crate::schema::authors::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    // 作者页的 url 唯一确定一个作者，显示的名字可能重复
+    let found = authors
+        .filter(url.eq(_url))
+        .first::<Author>(&mut *conn)
+        .optional()?;
+    if let Some(author) = found {
+        return Ok(author);
+    }
+
+    let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let new_author = NewAuthor {
+        name: _name,
+        url: _url,
+        comic_count: _comic_count,
+        updated_at: &now,
+    };
+    let row_id = diesel::insert_into(authors)
+        .values(&new_author)
+        .returning(id)
+        .get_result::<i32>(&mut *conn)?;
+    info!("find_or_create_author row_id: {:?}", row_id);
+
+    authors.find(row_id).first(&mut *conn)
+}
+
+pub fn get_author(author_id: i32) -> QueryResult<Author> {
+    use crate::schema::authors::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    authors.find(author_id).first(&mut *conn)
+}
+
+pub fn get_all_authors() -> QueryResult<Vec<Author>> {
+    use crate::schema::authors::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    authors.order(name.asc()).load::<Author>(&mut *conn)
+}
+
+pub fn update_author_refreshed(author_id: i32, _comic_count: i32) -> QueryResult<usize> {
+    use crate::schema::authors::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    diesel::update(authors.find(author_id))
+        .set((comic_count.eq(_comic_count), updated_at.eq(now)))
+        .execute(&mut *conn)
+}
+
+// 查询作者关联的所有任务 不包含字段 cache_json
+pub fn get_download_tasks_by_author_id(_author_id: i32) -> QueryResult<Vec<PartialDownloadTask>> {
+    use crate::schema::download_tasks::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    download_tasks
+        .filter(author_id.eq(_author_id))
+        .select((
+            id,
+            dl_type,
+            status,
+            local_path,
+            url,
+            author,
+            comic_name,
+            progress,
+            count,
+            now_count,
+            error_vec,
+            done,
+            category,
+            author_id,
+            weight,
+            queue_position,
+            pause_reason,
+        ))
+        .load::<PartialDownloadTask>(&mut *conn)
+}
+
+// 追加任务历史和这次下载各图片域名的统计
+pub fn insert_task_history(
+    history: &NewTaskHistory,
+    hosts: &Vec<NewHostHistory>,
+) -> QueryResult<()> {
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+    conn.transaction(|conn| {
+        diesel::insert_into(crate::schema::task_history::table)
+            .values(history)
+            .execute(conn)?;
+        if !hosts.is_empty() {
+            diesel::insert_into(crate::schema::host_history::table)
+                .values(hosts)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+// 最近的任务历史，task_id 为 0 时查询所有任务
+pub fn get_task_history(_task_id: i32, limit: i64) -> QueryResult<Vec<TaskHistory>> {
+    use crate::schema::task_history::dsl::*;
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let mut query = task_history.into_boxed();
+    if _task_id != 0 {
+        query = query.filter(task_id.eq(_task_id));
+    }
+    query
+        .order(id.desc())
+        .limit(limit)
+        .load::<TaskHistory>(&mut *conn)
+}
+
+// 按天（day）或按周（week）汇总，最近的在前
+pub fn get_history_totals(period: &str, limit: i64) -> QueryResult<Vec<HistoryTotal>> {
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let key = if period == "week" {
+        "strftime('%Y-W%W', created_at)"
+    } else {
+        "substr(created_at, 1, 10)"
+    };
+    let sql = format!(
+        r#"
+        SELECT {} AS period,
+            SUM(event = 'added') AS added,
+            SUM(event = 'finished') AS finished,
+            SUM(event = 'failed') AS failed,
+            SUM(bytes) AS bytes,
+            SUM(img_count) AS img_count,
+            SUM(duration_ms) AS duration_ms
+        FROM task_history
+        GROUP BY period
+        ORDER BY period DESC
+        LIMIT ?
+    "#,
+        key
+    );
+    diesel::sql_query(sql)
+        .bind::<diesel::sql_types::BigInt, _>(limit)
+        .load::<HistoryTotal>(&mut *conn)
+}
+
+// 根据下载历史计算平均每张图片的字节数，没有记录时返回 None
+pub fn get_average_page_bytes() -> QueryResult<Option<i64>> {
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let sql = r#"
+        SELECT SUM(bytes) / SUM(img_count) AS bytes
+        FROM task_history
+        WHERE img_count > 0
+    "#;
+    Ok(diesel::sql_query(sql)
+        .get_result::<AveragePageBytes>(&mut *conn)?
+        .bytes)
+}
+
+pub fn get_site_totals() -> QueryResult<Vec<SiteTotal>> {
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let sql = r#"
+        SELECT site,
+            COUNT(DISTINCT task_id) AS tasks,
+            SUM(event = 'finished') AS finished,
+            SUM(event = 'failed') AS failed,
+            SUM(bytes) AS bytes,
+            SUM(img_count) AS img_count,
+            SUM(error_count) AS error_count
+        FROM task_history
+        GROUP BY site
+        ORDER BY bytes DESC
+    "#;
+    diesel::sql_query(sql).load::<SiteTotal>(&mut *conn)
+}
+
+// 最慢（slowest）或失败率最高（failing）的图片域名
+pub fn get_host_totals(order: &str, limit: i64) -> QueryResult<Vec<HostTotal>> {
+    let mut conn = DB_CONNECTION.get().unwrap().lock().unwrap();
+
+    let order_by = if order == "failing" {
+        "failure_rate DESC, failures DESC"
+    } else {
+        "avg_ms DESC"
+    };
+    let sql = format!(
+        r#"
+        SELECT host,
+            SUM(requests) AS requests,
+            SUM(failures) AS failures,
+            SUM(bytes) AS bytes,
+            CAST(SUM(duration_ms) AS REAL) / MAX(SUM(requests), 1) AS avg_ms,
+            CAST(SUM(failures) AS REAL) / MAX(SUM(requests), 1) AS failure_rate
+        FROM host_history
+        GROUP BY host
+        ORDER BY {}
+        LIMIT ?
+    "#,
+        order_by
+    );
+    diesel::sql_query(sql)
+        .bind::<diesel::sql_types::BigInt, _>(limit)
+        .load::<HostTotal>(&mut *conn)
 }
 
 fn create_db_file() {
@@ -256,11 +1153,6 @@ fn db_file_exists() -> bool {
     Path::new(&db_path).exists()
 }
 
-fn get_db_path() -> String {
-    let home_dir = home::home_dir().unwrap();
-    home_dir
-        .join(".comic_dl_tauri/db/db.sqlite")
-        .to_str()
-        .unwrap()
-        .to_string()
+pub(crate) fn get_db_path() -> String {
+    app_paths().db_file().to_string_lossy().to_string()
 }
@@ -0,0 +1,77 @@
+use crate::db::get_average_page_bytes;
+use crate::SETTING;
+use log::error;
+use std::path::Path;
+
+// 设置为空时至少保留 1GB
+const DEFAULT_MIN_FREE_MB: u64 = 1024;
+// 没有下载历史时按每张图片 500KB 估算
+const DEFAULT_PAGE_BYTES: u64 = 500 * 1024;
+
+// 下载目录所在磁盘的可用空间和需要保留的空间
+#[derive(Debug, Clone, Copy)]
+pub struct DiskSpace {
+    pub free: u64,
+    pub min_free: u64,
+}
+
+impl DiskSpace {
+    pub fn is_low(&self) -> bool {
+        self.free < self.min_free
+    }
+
+    // 保留 min_free 之后还能使用的空间
+    pub fn usable(&self) -> u64 {
+        self.free.saturating_sub(self.min_free)
+    }
+}
+
+// 下载目录还不存在时检查最近的上级目录
+fn available_space(dir: &str) -> Option<u64> {
+    let mut path = Path::new(dir);
+    loop {
+        if path.exists() {
+            return match fs2::available_space(path) {
+                Ok(free) => Some(free),
+                Err(e) => {
+                    error!("available_space failed: {} e: {}", dir, e);
+                    None
+                }
+            };
+        }
+        path = path.parent()?;
+    }
+}
+
+// min_free_space 为 0 或无法获取可用空间时返回 None，不做检查
+pub fn check_disk() -> Option<DiskSpace> {
+    let (dir, min_free_mb) = {
+        let setting = SETTING.read().unwrap();
+        (
+            setting.download_dir.clone(),
+            setting
+                .min_free_space
+                .parse::<u64>()
+                .unwrap_or(DEFAULT_MIN_FREE_MB),
+        )
+    };
+    if min_free_mb == 0 {
+        return None;
+    }
+    Some(DiskSpace {
+        free: available_space(&dir)?,
+        min_free: min_free_mb * 1024 * 1024,
+    })
+}
+
+// 历史下载的平均图片大小
+pub fn average_page_bytes() -> u64 {
+    match get_average_page_bytes() {
+        Ok(Some(bytes)) if bytes > 0 => bytes as u64,
+        Ok(_) => DEFAULT_PAGE_BYTES,
+        Err(e) => {
+            error!("get_average_page_bytes failed: {}", e);
+            DEFAULT_PAGE_BYTES
+        }
+    }
+}
@@ -0,0 +1,529 @@
+use crate::adaptive::{forget_task, task_img_concurrency};
+use crate::bandwidth::set_bandwidth_limit;
+use crate::db::{get_download_task, update_batch_status, update_download_task_status_reason};
+use crate::disk::{average_page_bytes, check_disk, DiskSpace};
+use crate::img_pool::{register_task, unregister_task};
+use crate::models::PartialDownloadTask;
+use crate::stats::{end_task_stats, global_speed, task_speeds};
+use crate::{
+    run_join_set_current, run_join_set_juanhuafanwai, save_task_event, sort_tasks, DownloadEvent,
+    GlobalSpeed, StartAllData, APP_HANDLE, SETTING, TASKS,
+};
+use chrono::{Datelike, Local, Timelike};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+// 下载管理器接收的消息
+#[derive(Debug)]
+pub enum ManagerMsg {
+    // 有任务进入等待队列、设置改变或定时检查下载时间段，重新调度
+    Schedule,
+    // 任务的下载协程已经退出，释放位置
+    Finished(i32),
+}
+
+// 下载时间段，days 为星期几（周一为 0），start / end 格式 HH:MM，end 不大于 start 时跨过午夜
+// concurrent_task 为空或 0 时使用全局设置，bandwidth 单位 KB/s，为空或 0 时不限速
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleWindow {
+    pub days: Vec<u32>,
+    pub start: String,
+    pub end: String,
+    pub concurrent_task: String,
+    pub bandwidth: String,
+}
+
+// 当前生效的下载限制
+struct Limits {
+    concurrent_task: usize,
+    // 每秒字节数，0 不限速
+    bandwidth: u64,
+}
+
+static MANAGER_TX: OnceLock<UnboundedSender<ManagerMsg>> = OnceLock::new();
+// 正在运行下载协程的任务和它的取消令牌，协程退出后由管理器移除
+static RUNNING: LazyLock<Mutex<HashMap<i32, CancellationToken>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+// 发送和保存下载进度的间隔
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+// 磁盘空间是否低于设置的保留空间，用于只在状态改变时提示
+static DISK_LOW: AtomicBool = AtomicBool::new(false);
+// 移动或重命名图片时不开始新的任务，避免任务写入旧的路径
+static LIBRARY_LOCKED: AtomicBool = AtomicBool::new(false);
+
+// 在 app 的 runtime 上启动唯一的下载管理器
+pub fn init_download_manager() {
+    let (tx, rx) = unbounded_channel::<ManagerMsg>();
+    if MANAGER_TX.set(tx).is_ok() {
+        tauri::async_runtime::spawn(run_manager(rx));
+        // 定时发送下载进度和速度
+        tauri::async_runtime::spawn(async {
+            let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+            loop {
+                interval.tick().await;
+                emit_progress();
+            }
+        });
+        // 定时检查下载时间段的开始和结束
+        tauri::async_runtime::spawn(async {
+            let mut interval = tokio::time::interval(Duration::from_secs(30));
+            loop {
+                interval.tick().await;
+                send_manager(ManagerMsg::Schedule);
+            }
+        });
+        // 下载时更频繁地检查磁盘空间，空间不足时尽快暂停
+        tauri::async_runtime::spawn(async {
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                if RUNNING.lock().unwrap().is_empty() {
+                    continue;
+                }
+                if check_disk().is_some_and(|x| x.is_low()) {
+                    send_manager(ManagerMsg::Schedule);
+                }
+            }
+        });
+    }
+}
+
+pub fn send_manager(msg: ManagerMsg) {
+    match MANAGER_TX.get() {
+        Some(tx) => {
+            if let Err(e) = tx.send(msg) {
+                error!("send_manager failed: {}", e);
+            }
+        }
+        None => {
+            error!("download manager not init: {:?}", msg);
+        }
+    }
+}
+
+// 暂停或删除时取消正在下载的任务，协程会尽快停止请求并退出
+pub fn cancel_task(id: i32) {
+    if let Some(token) = RUNNING.lock().unwrap().get(&id) {
+        info!("cancel task: {}", id);
+        token.cancel();
+    }
+}
+
+// 没有正在下载或等待的任务时锁定图片库，锁定期间开始的任务保持等待
+// 和 schedule 一样持有 RUNNING 锁，检查后不会再有任务开始
+pub fn lock_library() -> Result<(), &'static str> {
+    let running = RUNNING.lock().unwrap();
+    let busy = !running.is_empty()
+        || TASKS
+            .read()
+            .unwrap()
+            .iter()
+            .any(|x| x.status == "downloading" || x.status == "waiting");
+    if busy {
+        return Err("pause all downloading and waiting tasks before moving files");
+    }
+    LIBRARY_LOCKED
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .map(|_| ())
+        .map_err(|_| "files are being moved, please wait")
+}
+
+// 解锁图片库，开始锁定期间等待的任务
+pub fn unlock_library() {
+    LIBRARY_LOCKED.store(false, Ordering::SeqCst);
+    send_manager(ManagerMsg::Schedule);
+}
+
+// 退出程序时取消所有任务，并等待协程保存状态后退出
+pub fn cancel_all_tasks(wait: Duration) {
+    for token in RUNNING.lock().unwrap().values() {
+        token.cancel();
+    }
+    let start = Instant::now();
+    while !RUNNING.lock().unwrap().is_empty() && start.elapsed() < wait {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn emit_task_status(id: i32, status: &str, pause_reason: &str) {
+    let app_lock = APP_HANDLE.read().unwrap().clone();
+    if let Some(app) = app_lock {
+        let _ = app.emit(
+            "task_status",
+            HashMap::from([
+                ("id", id.to_string()),
+                ("status", String::from(status)),
+                ("pause_reason", String::from(pause_reason)),
+            ]),
+        );
+    }
+}
+
+// 发送正在下载的任务的进度、速度和剩余时间，以及所有任务的速度
+fn emit_progress() {
+    let app = match APP_HANDLE.read().unwrap().clone() {
+        Some(app) => app,
+        None => return,
+    };
+    let speeds = task_speeds();
+    for (id, speed) in speeds.iter() {
+        let (status, error_vec) = {
+            let tasks = TASKS.read().unwrap();
+            match tasks.iter().find(|x| x.id == *id) {
+                Some(task) => (task.status.clone(), task.error_vec.clone()),
+                None => continue,
+            }
+        };
+        if status != "downloading" {
+            continue;
+        }
+        let progress = if speed.total > 0 {
+            (speed.now_count as f32) / (speed.total as f32) * 100.00
+        } else {
+            0.0
+        };
+        let _ = app.emit(
+            "progress",
+            DownloadEvent {
+                id: *id,
+                progress: format!("{:.2}", progress),
+                count: speed.total,
+                now_count: speed.now_count as i32,
+                error_vec,
+                status,
+                img_concurrency: task_img_concurrency(*id),
+                speed: speed.speed,
+                bytes: speed.bytes,
+                eta: speed.eta,
+            },
+        );
+    }
+    let (speed, bytes) = global_speed();
+    if !speeds.is_empty() || speed > 0 {
+        let _ = app.emit("global_speed", GlobalSpeed { speed, bytes });
+    }
+}
+
+// "HH:MM" 转为分钟数，"24:00" 为 1440
+fn parse_minutes(time: &str) -> Option<u32> {
+    let (hour, minute) = time.trim().split_once(':')?;
+    let hour = hour.parse::<u32>().ok()?;
+    let minute = minute.parse::<u32>().ok()?;
+    if hour > 24 || minute > 59 || (hour == 24 && minute > 0) {
+        return None;
+    }
+    Some(hour * 60 + minute)
+}
+
+fn window_contains(window: &ScheduleWindow, weekday: u32, minutes: u32) -> bool {
+    let (start, end) = match (parse_minutes(&window.start), parse_minutes(&window.end)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return false,
+    };
+    if start < end {
+        window.days.contains(&weekday) && start <= minutes && minutes < end
+    } else {
+        // 跨过午夜的时间段属于开始的那天，start 等于 end 时为全天
+        (window.days.contains(&weekday) && minutes >= start)
+            || (window.days.contains(&((weekday + 6) % 7)) && minutes < end)
+    }
+}
+
+// 没有设置下载时间段时使用全局设置，设置了但现在不在任何时间段内时返回 None
+fn current_limits() -> Option<Limits> {
+    let setting = SETTING.read().unwrap();
+    let global = setting.concurrent_task.parse::<usize>().unwrap_or(1);
+    if setting.schedule.is_empty() {
+        return Some(Limits {
+            concurrent_task: global,
+            bandwidth: 0,
+        });
+    }
+    let now = Local::now();
+    let weekday = now.weekday().num_days_from_monday();
+    let minutes = now.hour() * 60 + now.minute();
+    setting
+        .schedule
+        .iter()
+        .find(|window| window_contains(window, weekday, minutes))
+        .map(|window| Limits {
+            concurrent_task: window
+                .concurrent_task
+                .parse::<usize>()
+                .ok()
+                .filter(|x| *x > 0)
+                .unwrap_or(global),
+            bandwidth: window.bandwidth.parse::<u64>().unwrap_or(0) * 1024,
+        })
+}
+
+// 下载时间段结束（schedule）或磁盘空间不足（disk），把正在下载的任务放回等待队列并取消，
+// 等待中的任务同样标记原因，条件恢复后会自动继续
+fn pause_running(running: &HashMap<i32, CancellationToken>, reason: &str) {
+    let paused: Vec<i32> = {
+        let mut tasks = TASKS.write().unwrap();
+        tasks
+            .iter_mut()
+            .filter(|x| {
+                (x.status == "downloading" && running.contains_key(&x.id))
+                    || (x.status == "waiting" && x.pause_reason != reason)
+            })
+            .map(|task| {
+                task.status = String::from("waiting");
+                task.pause_reason = String::from(reason);
+                task.id
+            })
+            .collect()
+    };
+    for id in paused.iter() {
+        info!("pause by {}: {}", reason, id);
+        if let Err(e) = update_download_task_status_reason(*id, "waiting", reason) {
+            error!("update task status failed: {} e: {}", id, e);
+        }
+        emit_task_status(*id, "waiting", reason);
+        if let Some(token) = running.get(id) {
+            token.cancel();
+        }
+    }
+    if !paused.is_empty() {
+        sort_tasks();
+    }
+}
+
+// 记录磁盘空间是否不足，状态改变时提示，返回是否不足
+fn update_disk_low(disk: &Option<DiskSpace>) -> bool {
+    let is_low = disk.is_some_and(|x| x.is_low());
+    let was_low = DISK_LOW.swap(is_low, Ordering::Relaxed);
+    if is_low == was_low {
+        return is_low;
+    }
+    let msg = match disk {
+        Some(disk) if is_low => format!(
+            "Low disk space: {} MB free, less than {} MB, all downloads paused",
+            disk.free / 1024 / 1024,
+            disk.min_free / 1024 / 1024
+        ),
+        _ => String::from("Disk space available, downloads resumed"),
+    };
+    info!("update_disk_low: {}", &msg);
+    let app_lock = APP_HANDLE.read().unwrap().clone();
+    if let Some(app) = app_lock {
+        let _ = app.emit(
+            if is_low {
+                "err_msg_main"
+            } else {
+                "info_msg_main"
+            },
+            &msg,
+        );
+    }
+    is_low
+}
+
+// 把任务放入等待队列，已经在下载或等待的任务不变，返回状态改变的任务
+pub fn enqueue_tasks(ids: &Vec<i32>) -> Vec<StartAllData> {
+    let mut data_for_db: Vec<StartAllData> = Vec::new();
+    {
+        let mut tasks = TASKS.write().unwrap();
+        for task in tasks.iter_mut() {
+            if ids.contains(&task.id) && task.status != "downloading" && task.status != "waiting" {
+                task.status = String::from("waiting");
+                task.pause_reason = String::from("");
+                data_for_db.push(StartAllData {
+                    id: task.id,
+                    status: String::from("waiting"),
+                });
+            }
+        }
+    }
+    info!("enqueue_tasks data_for_db: {:?}", &data_for_db);
+    update_batch_status(&data_for_db);
+    for data in data_for_db.iter() {
+        emit_task_status(data.id, &data.status, "");
+    }
+    sort_tasks();
+
+    send_manager(ManagerMsg::Schedule);
+    data_for_db
+}
+
+async fn run_manager(mut rx: UnboundedReceiver<ManagerMsg>) {
+    while let Some(msg) = rx.recv().await {
+        match msg {
+            ManagerMsg::Schedule => {}
+            ManagerMsg::Finished(id) => {
+                info!("id: {} download finished", id);
+                RUNNING.lock().unwrap().remove(&id);
+                unregister_task(id);
+                forget_task(id);
+                end_task_stats(id);
+            }
+        }
+        schedule();
+    }
+}
+
+// 有空位时按队列顺序把 waiting 的任务改为 downloading 并开始下载
+// 估算剩余图片需要的空间，放不下的任务保持等待并标记 disk
+fn schedule() {
+    let limits = current_limits();
+    set_bandwidth_limit(limits.as_ref().map(|x| x.bandwidth).unwrap_or(0));
+    let mut running = RUNNING.lock().unwrap();
+    if LIBRARY_LOCKED.load(Ordering::SeqCst) {
+        return;
+    }
+    let concurrent_count = match limits {
+        Some(limits) => limits.concurrent_task,
+        None => {
+            pause_running(&running, "schedule");
+            return;
+        }
+    };
+    let disk = check_disk();
+    if update_disk_low(&disk) {
+        pause_running(&running, "disk");
+        return;
+    }
+    let change_count = concurrent_count.saturating_sub(running.len());
+    let page_bytes = if disk.is_some() {
+        average_page_bytes()
+    } else {
+        0
+    };
+    let mut usable = disk.map(|x| x.usable());
+    let total_usable = usable;
+
+    // 等待原因改变的任务
+    let mut reasons: Vec<(i32, &str)> = Vec::new();
+    // 需要的空间超过全部可用空间的任务，等待也不会有足够的空间
+    let mut too_large: Vec<(i32, String, u64)> = Vec::new();
+    let will_start: Vec<PartialDownloadTask> = {
+        let mut tasks = TASKS.write().unwrap();
+        let mut waiting: Vec<&mut PartialDownloadTask> = tasks
+            .iter_mut()
+            .filter(|x| x.status == "waiting" && !running.contains_key(&x.id))
+            .collect();
+        // 按队列顺序
+        waiting.sort_by_key(|x| x.queue_position);
+        let mut will_start = Vec::new();
+        for task in waiting {
+            let need = (task.count - task.now_count).max(0) as u64 * page_bytes;
+            if total_usable.is_some_and(|x| x < need) {
+                task.status = String::from("failed");
+                task.pause_reason = String::from("");
+                too_large.push((task.id, task.comic_name.clone(), need));
+                continue;
+            }
+            if usable.is_some_and(|x| x < need) {
+                if task.pause_reason != "disk" {
+                    task.pause_reason = String::from("disk");
+                    reasons.push((task.id, "disk"));
+                }
+                continue;
+            }
+            if will_start.len() >= change_count {
+                if !task.pause_reason.is_empty() {
+                    task.pause_reason = String::from("");
+                    reasons.push((task.id, ""));
+                }
+                continue;
+            }
+            usable = usable.map(|x| x - need);
+            task.status = String::from("downloading");
+            task.pause_reason = String::from("");
+            will_start.push(task.clone());
+        }
+        will_start
+    };
+    for (id, reason) in reasons.iter() {
+        info!("waiting task: {} reason: {}", id, reason);
+        if let Err(e) = update_download_task_status_reason(*id, "waiting", reason) {
+            error!("update task status failed: {} e: {}", id, e);
+        }
+        emit_task_status(*id, "waiting", reason);
+    }
+    for (id, comic_name, need) in too_large.iter() {
+        let msg = format!(
+            "{} needs about {} MB but only {} MB can be used on the download disk, task failed",
+            comic_name,
+            need / 1024 / 1024,
+            total_usable.unwrap_or(0) / 1024 / 1024
+        );
+        info!("task too large: {} {}", id, &msg);
+        if let Err(e) = update_download_task_status_reason(*id, "failed", "") {
+            error!("update task status failed: {} e: {}", id, e);
+        }
+        emit_task_status(*id, "failed", "");
+        if let Some(app) = APP_HANDLE.read().unwrap().clone() {
+            let _ = app.emit("err_msg_main", &msg);
+        }
+    }
+    if will_start.is_empty() {
+        return;
+    }
+
+    for task in will_start {
+        info!("will start {:?}", &task);
+        if let Err(e) = update_download_task_status_reason(task.id, "downloading", "") {
+            error!("update task status failed: {} e: {}", task.id, e);
+        }
+        emit_task_status(task.id, "downloading", "");
+        let token = CancellationToken::new();
+        running.insert(task.id, token.clone());
+        save_task_event(&task, "started");
+        register_task(task.id, task.weight);
+        spawn_download(task, token);
+    }
+    drop(running);
+    sort_tasks();
+}
+
+fn spawn_download(task: PartialDownloadTask, token: CancellationToken) {
+    tauri::async_runtime::spawn(async move {
+        let id = task.id;
+        let thread_error_msg = format!(
+            "The download task crashed: id: {} comic_name: {} dl_type: {}",
+            &task.id, &task.comic_name, &task.dl_type,
+        );
+        if let Err(e) = tauri::async_runtime::spawn(run_download(task, token)).await {
+            error!("thread_error_msg: {} e: {:?}", &thread_error_msg, e);
+            let app_lock = APP_HANDLE.read().unwrap().clone();
+            if let Some(app) = app_lock {
+                let _ = app.emit("err_msg_main", &thread_error_msg);
+            }
+        }
+        send_manager(ManagerMsg::Finished(id));
+    });
+}
+
+async fn run_download(task: PartialDownloadTask, token: CancellationToken) {
+    let complete_current_task = match get_download_task(task.id) {
+        Ok(res) => res,
+        Err(e) => {
+            error!(
+                "run_download get_download_task failed: {} e: {}",
+                task.id, e
+            );
+            return;
+        }
+    };
+    match task.dl_type.as_str() {
+        "juan" | "hua" | "fanwai" | "category" => {
+            run_join_set_juanhuafanwai(complete_current_task, token).await
+        }
+        "current" => run_join_set_current(complete_current_task, token).await,
+        _ => {
+            error!(
+                "run_download unknown dl_type: {} {}",
+                task.id, &task.dl_type
+            );
+        }
+    }
+}
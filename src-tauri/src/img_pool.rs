@@ -0,0 +1,142 @@
+use log::info;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+// 所有任务共用的图片下载位置，空出的位置优先分给 占用数/权重 最小的任务
+struct PoolState {
+    capacity: usize,
+    in_use: usize,
+    // task_id -> 正在下载的图片数
+    task_in_use: HashMap<i32, usize>,
+    // 正在运行的任务的权重
+    weights: HashMap<i32, u32>,
+    waiters: HashMap<i32, VecDeque<oneshot::Sender<()>>>,
+    // 占用相同时分给最久没有分配过的任务
+    serve_count: u64,
+    last_served: HashMap<i32, u64>,
+}
+
+static IMG_POOL: LazyLock<Mutex<PoolState>> = LazyLock::new(|| {
+    Mutex::new(PoolState {
+        capacity: 10,
+        in_use: 0,
+        task_in_use: HashMap::new(),
+        weights: HashMap::new(),
+        waiters: HashMap::new(),
+        serve_count: 0,
+        last_served: HashMap::new(),
+    })
+});
+
+// 占用一个图片下载位置，drop 时归还
+#[derive(Debug)]
+pub struct ImgSlot {
+    task_id: i32,
+}
+
+impl Drop for ImgSlot {
+    fn drop(&mut self) {
+        let mut state = IMG_POOL.lock().unwrap();
+        state.in_use = state.in_use.saturating_sub(1);
+        if let Some(count) = state.task_in_use.get_mut(&self.task_id) {
+            *count -= 1;
+            if *count == 0 {
+                state.task_in_use.remove(&self.task_id);
+            }
+        }
+        dispatch(&mut state);
+    }
+}
+
+fn dispatch(state: &mut PoolState) {
+    while state.in_use < state.capacity {
+        let next = state
+            .waiters
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|(task_id, _)| *task_id)
+            .min_by(|a, b| compare_share(state, *a, *b));
+        let task_id = match next {
+            Some(task_id) => task_id,
+            None => break,
+        };
+        let tx = state
+            .waiters
+            .get_mut(&task_id)
+            .and_then(|queue| queue.pop_front())
+            .unwrap();
+        // 等待方已取消时跳过
+        if tx.send(()).is_ok() {
+            state.in_use += 1;
+            *state.task_in_use.entry(task_id).or_insert(0) += 1;
+            state.serve_count += 1;
+            let serve_count = state.serve_count;
+            state.last_served.insert(task_id, serve_count);
+        }
+    }
+    state.waiters.retain(|_, queue| !queue.is_empty());
+}
+
+// 比较 占用数/权重，相同时比较上次分配的顺序
+fn compare_share(state: &PoolState, a: i32, b: i32) -> Ordering {
+    let in_use = |task_id: i32| *state.task_in_use.get(&task_id).unwrap_or(&0) as u64;
+    let weight = |task_id: i32| *state.weights.get(&task_id).unwrap_or(&1) as u64;
+    (in_use(a) * weight(b))
+        .cmp(&(in_use(b) * weight(a)))
+        .then_with(|| {
+            let last = |task_id: i32| *state.last_served.get(&task_id).unwrap_or(&0);
+            last(a).cmp(&last(b))
+        })
+}
+
+// 等待空出的图片下载位置，任务取消时返回 None
+pub async fn acquire_img_slot(task_id: i32, token: &CancellationToken) -> Option<ImgSlot> {
+    let (tx, mut rx) = oneshot::channel::<()>();
+    {
+        let mut state = IMG_POOL.lock().unwrap();
+        state.waiters.entry(task_id).or_default().push_back(tx);
+        dispatch(&mut state);
+    }
+    tokio::select! {
+        res = &mut rx => res.ok().map(|_| ImgSlot { task_id }),
+        _ = token.cancelled() => {
+            rx.close();
+            // 取消的同时已经分到位置，归还
+            if rx.try_recv().is_ok() {
+                drop(ImgSlot { task_id });
+            }
+            None
+        }
+    }
+}
+
+// 同时下载的图片总数，来自设置 concurrent_img
+pub fn set_img_capacity(capacity: usize) {
+    let mut state = IMG_POOL.lock().unwrap();
+    info!("set_img_capacity: {}", capacity);
+    state.capacity = capacity.max(1);
+    dispatch(&mut state);
+}
+
+pub fn register_task(task_id: i32, weight: i32) {
+    let mut state = IMG_POOL.lock().unwrap();
+    state.weights.insert(task_id, weight.max(1) as u32);
+}
+
+pub fn unregister_task(task_id: i32) {
+    let mut state = IMG_POOL.lock().unwrap();
+    state.weights.remove(&task_id);
+    state.waiters.remove(&task_id);
+    state.last_served.remove(&task_id);
+}
+
+// 修改正在运行的任务的权重
+pub fn update_task_weight(task_id: i32, weight: i32) {
+    let mut state = IMG_POOL.lock().unwrap();
+    if let Some(w) = state.weights.get_mut(&task_id) {
+        *w = weight.max(1) as u32;
+    }
+}
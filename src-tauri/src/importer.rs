@@ -0,0 +1,360 @@
+use crate::antbyw::{CurrentElement, Img};
+use crate::db::{create_download_task, find_tasks_by_dl_type_and_url, update_page_file_paths};
+use crate::models::DownloadTask;
+use crate::utils::{
+    get_dir_name, get_file_name_without_extension, is_image_file, parse_chapter_number,
+};
+use log::{error, info};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+// 扫描到的一个漫画目录
+#[derive(Debug, Clone)]
+pub struct LocalComic {
+    pub dir: PathBuf,
+    pub author: String,
+    pub comic_name: String,
+    pub dl_type: String,
+    pub category: String,
+    // 章节名和章节中的图片，按顺序排列
+    pub chapters: Vec<(String, Vec<PathBuf>)>,
+}
+
+// 导入的结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportResult {
+    pub imported: usize,
+    // 已经导入过的目录
+    pub skipped: usize,
+    pub failed: usize,
+    pub pages: usize,
+}
+
+// 找出所有直接包含图片的目录，跳过隐藏目录
+fn collect_image_dirs(dir: &Path, res: &mut BTreeMap<PathBuf, Vec<PathBuf>>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!(
+                "collect_image_dirs read_dir failed: {} e: {}",
+                dir.display(),
+                e
+            );
+            return;
+        }
+    };
+    let mut images = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if !get_dir_name(&path).is_some_and(|x| x.starts_with('.')) {
+                collect_image_dirs(&path, res);
+            }
+        } else if is_image_file(&path) {
+            images.push(path);
+        }
+    }
+    if !images.is_empty() {
+        // 按文件名中的数字排序，0.jpg 1.jpg 10.jpg
+        images.sort_by_key(|path| {
+            let stem = get_file_name_without_extension(path).unwrap_or_default();
+            (stem.parse::<u64>().unwrap_or(u64::MAX), stem)
+        });
+        res.insert(dir.to_path_buf(), images);
+    }
+}
+
+// 目录名 {comic}_{category}，单行本 单话 番外篇 对应原来的下载类型，其余作为类别
+fn parse_comic_dir(name: &str) -> (String, String, String) {
+    match name.rsplit_once('_') {
+        Some((comic, category)) if !comic.is_empty() => {
+            let dl_type = match category {
+                "单行本" => "juan",
+                "单话" => "hua",
+                "番外篇" => "fanwai",
+                _ => "category",
+            };
+            let category = if dl_type == "category" { category } else { "" };
+            (comic.to_string(), dl_type.to_string(), category.to_string())
+        }
+        _ => (name.to_string(), String::from("category"), String::new()),
+    }
+}
+
+// 上级目录在 root 中时作为作者
+fn author_of(dir: &Path, root: &Path) -> String {
+    match dir.parent() {
+        Some(parent) if parent != root && parent.starts_with(root) => {
+            get_dir_name(parent).unwrap_or_default()
+        }
+        _ => String::new(),
+    }
+}
+
+// 按 {author}/{comic}_{category}/{chapter}/{page} 的结构扫描目录
+// 只有一个图片目录或者直接在 root 下，且上级目录名不是 {comic}_{category} 时，作为 current 任务 {comic}/{page}
+pub fn scan_folder(root: &Path) -> Vec<LocalComic> {
+    let mut image_dirs: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    collect_image_dirs(root, &mut image_dirs);
+
+    // 按上级目录分组
+    let mut groups: BTreeMap<PathBuf, Vec<(String, Vec<PathBuf>)>> = BTreeMap::new();
+    for (dir, images) in image_dirs.into_iter() {
+        let parent = dir.parent().unwrap_or(&dir).to_path_buf();
+        let name = get_dir_name(&dir).unwrap_or_default();
+        groups.entry(parent).or_default().push((name, images));
+    }
+
+    let mut comics = Vec::new();
+    for (parent, mut chapters) in groups.into_iter() {
+        let parent_name = get_dir_name(&parent).unwrap_or_default();
+        let (comic_name, dl_type, category) = parse_comic_dir(&parent_name);
+        // root 下直接是多个图片目录时是多个 current 任务，不是一个漫画
+        let parsed = dl_type != "category" || !category.is_empty();
+        let is_comic_dir =
+            parent.starts_with(root) && (parsed || (parent != root && chapters.len() > 1));
+        if is_comic_dir {
+            chapters.sort_by(|a, b| {
+                parse_chapter_number(&a.0)
+                    .cmp(&parse_chapter_number(&b.0))
+                    .then_with(|| a.0.cmp(&b.0))
+            });
+            comics.push(LocalComic {
+                author: author_of(&parent, root),
+                dir: parent,
+                comic_name,
+                dl_type,
+                category,
+                chapters,
+            });
+        } else {
+            for (name, images) in chapters.into_iter() {
+                let dir = parent.join(&name);
+                comics.push(LocalComic {
+                    author: author_of(&dir, root),
+                    dir,
+                    comic_name: name.clone(),
+                    dl_type: String::from("current"),
+                    category: String::new(),
+                    chapters: vec![(name, images)],
+                });
+            }
+        }
+    }
+    comics
+}
+
+fn file_url(path: &Path) -> String {
+    Url::from_file_path(path)
+        .map(|x| x.to_string())
+        .unwrap_or_default()
+}
+
+// 把扫描到的漫画添加为已完成的任务，没有来源网址时使用目录的 file:// 网址，相同网址的任务不重复导入
+pub fn import_comic(
+    comic: &LocalComic,
+    source_url: Option<&str>,
+) -> Result<Option<DownloadTask>, String> {
+    let url = match source_url {
+        Some(url) => url.to_string(),
+        None => Url::from_directory_path(&comic.dir)
+            .map(|x| x.to_string())
+            .unwrap_or_default(),
+    };
+    let exists = find_tasks_by_dl_type_and_url(&comic.dl_type, &url).map_err(|e| e.to_string())?;
+    if !exists.is_empty() {
+        info!("import_comic skip imported: {}", comic.dir.display());
+        return Ok(None);
+    }
+
+    let groups: Vec<CurrentElement> = comic
+        .chapters
+        .iter()
+        .map(|(name, images)| CurrentElement {
+            name: name.clone(),
+            // current 任务的图片直接在漫画目录中
+            href: if comic.dl_type == "current" {
+                file_url(&comic.dir)
+            } else {
+                file_url(&comic.dir.join(name))
+            },
+            imgs: images
+                .iter()
+                .map(|path| Img {
+                    href: file_url(path),
+                    done: true,
+                    alt_href: None,
+                })
+                .collect(),
+            count: images.len(),
+            done: true,
+        })
+        .collect();
+    let count: i32 = groups.iter().map(|x| x.count as i32).sum();
+    let task = create_download_task(
+        &comic.dl_type,
+        "finished",
+        "",
+        &groups,
+        &url,
+        &comic.author,
+        &comic.comic_name,
+        "100.00",
+        count,
+        count,
+        "",
+        true,
+        &comic.category,
+        0,
+    )
+    .map_err(|e| e.to_string())?;
+
+    // 记录图片的实际路径，之后可以重命名和移动
+    let paths: Vec<(i32, i32, String)> = comic
+        .chapters
+        .iter()
+        .enumerate()
+        .flat_map(|(group_index, (_, images))| {
+            images.iter().enumerate().map(move |(page_index, path)| {
+                (
+                    group_index as i32,
+                    page_index as i32,
+                    path.to_string_lossy().to_string(),
+                )
+            })
+        })
+        .collect();
+    update_page_file_paths(task.id, &paths).map_err(|e| e.to_string())?;
+    info!("import_comic id: {} dir: {}", task.id, comic.dir.display());
+    Ok(Some(task))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 在临时目录中按相对路径创建图片文件，目录名不含 _ 以免被当作 {comic}_{category}
+    fn make_root(name: &str, files: &[&str]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("comic-dl-scan-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        root
+    }
+
+    fn summary(comics: &[LocalComic]) -> Vec<(String, String, String, String, Vec<String>)> {
+        comics
+            .iter()
+            .map(|x| {
+                (
+                    x.author.clone(),
+                    x.comic_name.clone(),
+                    x.dl_type.clone(),
+                    x.category.clone(),
+                    x.chapters.iter().map(|(name, _)| name.clone()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scan_flat_current_dirs() {
+        let root = make_root("flat", &["A/0.jpg", "A/1.jpg", "B/0.jpg"]);
+        let comics = scan_folder(&root);
+        assert_eq!(
+            summary(&comics),
+            vec![
+                (
+                    "".into(),
+                    "A".into(),
+                    "current".into(),
+                    "".into(),
+                    vec!["A".into()]
+                ),
+                (
+                    "".into(),
+                    "B".into(),
+                    "current".into(),
+                    "".into(),
+                    vec!["B".into()]
+                ),
+            ]
+        );
+        assert_eq!(comics[0].chapters[0].1.len(), 2);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn scan_comic_category_dirs() {
+        let root = make_root(
+            "category",
+            &[
+                "漫画_单话/第2话/0.jpg",
+                "漫画_单话/第1话/0.jpg",
+                "短篇集_短篇/第1话/0.jpg",
+            ],
+        );
+        let comics = scan_folder(&root);
+        assert_eq!(
+            summary(&comics),
+            vec![
+                (
+                    "".into(),
+                    "漫画".into(),
+                    "hua".into(),
+                    "".into(),
+                    vec!["第1话".into(), "第2话".into()]
+                ),
+                (
+                    "".into(),
+                    "短篇集".into(),
+                    "category".into(),
+                    "短篇".into(),
+                    vec!["第1话".into()]
+                ),
+            ]
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn scan_author_dirs() {
+        let root = make_root(
+            "author",
+            &[
+                "作者/漫画_单行本/第1卷/0.jpg",
+                "作者/漫画_单行本/第2卷/0.jpg",
+                "作者/另一部/第1话/0.jpg",
+                "作者/另一部/第2话/0.jpg",
+            ],
+        );
+        let comics = scan_folder(&root);
+        assert_eq!(
+            summary(&comics),
+            vec![
+                (
+                    "作者".into(),
+                    "另一部".into(),
+                    "category".into(),
+                    "".into(),
+                    vec!["第1话".into(), "第2话".into()]
+                ),
+                (
+                    "作者".into(),
+                    "漫画".into(),
+                    "juan".into(),
+                    "".into(),
+                    vec!["第1卷".into(), "第2卷".into()]
+                ),
+            ]
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -1,49 +1,93 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 // use tauri::{Manager, PhysicalPosition, Position};
+mod adaptive;
 mod antbyw;
+mod backup;
+mod bandwidth;
+mod cache;
 mod db;
+mod disk;
+mod download_manager;
+mod img_pool;
+mod importer;
 mod log_init;
 mod mangadex;
 pub mod models;
+mod naming;
+mod paths;
 // mod queue_rwlock;
 pub mod schema;
+mod stats;
 mod utils;
 
-use antbyw::{handle_html, CurrentElement, DataWrapper, HandleHtmlRes, Img};
-use bytes::Bytes;
+use adaptive::{acquire_host, get_host, report, task_img_concurrency, Outcome};
+use antbyw::{
+    handle_html, preview_chapters, remove_old_author_caches, select_chapters, AuthorElement,
+    CacheMode, ChapterPreviewGroup, ChapterSelection, CurrentElement, DataWrapper, HandleHtmlRes,
+    Img,
+};
+use backup::{backup_to, default_backup_name, init_backup_scheduler, restore_from, BackupInfo};
+use bandwidth::consume_bandwidth;
+use bytes::{Bytes, BytesMut};
+use cache::{
+    cache_stats, clear_task_cache, init_cache_manager, prune_cache, CacheStats, PruneResult,
+};
 use db::{
-    create_download_task, create_table, delete_batch_status_not_downloading, delete_download_task,
-    find_tasks_by_dl_type_and_url, get_all_download_tasks, get_download_task, init_db,
-    update_batch_status, update_download_task_progress, update_download_task_progress_error,
-    update_download_task_status,
+    append_task_pages, create_download_task, create_table, delete_batch_status_not_downloading,
+    delete_download_task, find_or_create_author, find_tasks_by_dl_type_and_url, get_all_authors,
+    get_all_download_tasks, get_author, get_chapter_hrefs_by_task_ids, get_download_task,
+    get_download_tasks_by_author_id, get_failed_pages, get_history_totals, get_host_totals,
+    get_site_totals, get_task_chapter_progress, get_task_groups, get_task_history, init_db,
+    insert_task_history, reset_interrupted_tasks, update_author_refreshed, update_batch_status,
+    update_download_task_progress, update_download_task_progress_error,
+    update_download_task_status_reason, update_download_task_weight, update_page_alt_href,
+    update_page_result, update_queue_positions,
+};
+use download_manager::{
+    cancel_all_tasks, cancel_task, enqueue_tasks, init_download_manager, lock_library,
+    send_manager, unlock_library, ManagerMsg, ScheduleWindow, PROGRESS_INTERVAL,
 };
 use image::{load_from_memory, ImageFormat};
+use img_pool::{acquire_img_slot, set_img_capacity, update_task_weight, ImgSlot};
+use importer::{import_comic, scan_folder, ImportResult};
 use log::{error, info};
 use log_init::init_log;
 use mangadex::handle_mangadex;
-use models::{DownloadTask, PartialDownloadTask};
+use models::{
+    ChapterProgress, DownloadTask, HistoryTotal, HostTotal, NewHostHistory, NewTaskHistory,
+    PartialDownloadTask, SiteTotal, TaskHistory,
+};
+use naming::{
+    chapter_dirs, comic_type, count_library_files, name_rules, page_file, relocate_all, rename_all,
+    task_dir, task_template, validate_template, RenameResult,
+};
+use paths::app_paths;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use tauri_plugin_notification::NotificationExt;
+use url::Url;
 
 // use queue_rwlock::QueuedRwLock;
 use reqwest;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use stats::{
+    record_bytes, record_img_done, record_request, run_stats, start_task_stats, task_bytes,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, LazyLock, RwLock};
-use std::thread::spawn;
 use tauri::{AppHandle, Emitter, Manager};
-use tokio::runtime::Runtime;
-use tokio::sync::OwnedSemaphorePermit;
-use tokio::sync::Semaphore;
-use tokio::time::{timeout, Duration};
+use tokio::time::{timeout, Duration, Instant};
+use tokio_util::sync::CancellationToken;
 use utils::{
-    clean_string, create_cache_dir, get_second_level_domain, read_from_json, save_to_json,
-    StatusCode,
+    clean_string, create_cache_dir, get_second_level_domain, get_url_query, read_from_json,
+    save_to_json, StatusCode,
 };
 
 pub static TASKS: RwLock<Vec<PartialDownloadTask>> = RwLock::new(Vec::new());
+// 重试失败图片时只下载的图片 (group_index, index)，任务开始下载时取出
+pub static RETRY_SELECTION: LazyLock<RwLock<HashMap<i32, HashSet<(usize, usize)>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
 pub static APP_HANDLE: LazyLock<RwLock<Option<AppHandle>>> = LazyLock::new(|| RwLock::new(None));
 pub static SETTING: LazyLock<RwLock<Setting>> = LazyLock::new(|| {
     RwLock::new(Setting {
@@ -52,6 +96,16 @@ pub static SETTING: LazyLock<RwLock<Setting>> = LazyLock::new(|| {
         concurrent_img: String::from("10"),
         img_timeout: String::from("5"),
         img_retry_count: String::from("3"),
+        auto_resume: String::from("false"),
+        auto_concurrent_img: String::from("false"),
+        min_free_space: String::from("1024"),
+        name_template: String::from(""),
+        filename_rules: String::from("windows"),
+        backup_interval: String::from("0"),
+        backup_keep: String::from("7"),
+        cache_max_size: String::from("500"),
+        cache_ttl: String::from("30"),
+        schedule: Vec::new(),
     })
 });
 #[derive(Debug, Clone)]
@@ -59,7 +113,26 @@ pub struct DownloadResult {
     group_index: usize,
     index: usize,
     error_msg: String,
+    href: String,
+    save_path: String,
+}
+
+// 下载失败的图片，序列化后存入 error_vec
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedImg {
+    group_index: usize,
+    index: usize,
+    href: String,
     save_path: String,
+    error_msg: String,
+}
+
+// 重试时替换的备用图片地址
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlternateImg {
+    group_index: usize,
+    index: usize,
+    href: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -70,6 +143,21 @@ pub struct DownloadEvent {
     now_count: i32,
     error_vec: String,
     status: String,
+    // 当前生效的图片并发数
+    img_concurrency: usize,
+    // 最近几秒的下载速度 bytes/s
+    speed: u64,
+    // 本次下载的字节数
+    bytes: u64,
+    // 剩余秒数，无法估算时为 -1
+    eta: i64,
+}
+
+// 所有任务的下载速度
+#[derive(Debug, Clone, Serialize)]
+pub struct GlobalSpeed {
+    speed: u64,
+    bytes: u64,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -91,12 +179,54 @@ pub struct Setting {
     concurrent_img: String,
     img_timeout: String,
     img_retry_count: String,
+    // 启动时是否继续上次未完成的下载 "true" / "false"
+    #[serde(default)]
+    auto_resume: String,
+    // 根据错误率和延迟自动调整每个图片域名的并发数，concurrent_img 为上限 "true" / "false"
+    #[serde(default)]
+    auto_concurrent_img: String,
+    // 下载目录所在磁盘至少保留的空间（MB），低于时暂停所有任务，"0" 不检查
+    #[serde(default)]
+    min_free_space: String,
+    // 图片保存路径的模板，为空时使用默认目录结构，见 naming.rs
+    #[serde(default)]
+    name_template: String,
+    // 文件名规则 "windows" / "unix"，windows 规则同样适用于 exFAT 磁盘
+    #[serde(default)]
+    filename_rules: String,
+    // 自动备份的间隔（小时），"0" 不自动备份
+    #[serde(default)]
+    backup_interval: String,
+    // 自动备份保留的份数
+    #[serde(default)]
+    backup_keep: String,
+    // html_cache 和 json_cache 的大小上限（MB），"0" 不限制
+    #[serde(default)]
+    cache_max_size: String,
+    // 超过天数没有使用的缓存会被删除，"0" 不删除
+    #[serde(default)]
+    cache_ttl: String,
+    // 下载时间段，为空时不限制
+    #[serde(default)]
+    schedule: Vec<ScheduleWindow>,
 }
 
+// 作者页面的汇总信息，由数据库中关联的任务计算
 #[derive(Debug, Clone, Serialize)]
-pub struct GetDownloadingCount {
+pub struct AuthorSummary {
+    id: i32,
+    name: String,
+    url: String,
+    updated_at: String,
+    comic_count: i32,
+    queued_comic_count: i32,
+    task_count: i32,
+    finished_count: i32,
+    failed_count: i32,
+    downloading_count: i32,
     count: i32,
-    downloading_ids: Vec<i32>,
+    now_count: i32,
+    progress: String,
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -124,12 +254,14 @@ pub fn run() {
                 app.emit("err_msg_main", format!("init log failed!"))
                     .unwrap();
             };
+            info!("app paths: {:?}", app_paths());
             // 创建缓存目录
             if let Err(e) = create_cache_dir() {
                 error!("create cache dir failed: {}", e.to_string());
                 app.emit("err_msg_main", format!("create cache dir failed!"))
                     .unwrap();
             };
+            let mut interrupted_ids: Vec<i32> = Vec::new();
             // 初始化数据库
             if let Err(e) = init_db() {
                 error!("{}", e.to_string());
@@ -140,6 +272,16 @@ pub fn run() {
                     error!("create_table failed: {}", e.to_string());
                     app.emit("err_msg_main", e.to_string()).unwrap();
                 } else {
+                    // 重置上次中断的任务
+                    match reset_interrupted_tasks() {
+                        Ok(ids) => {
+                            info!("reset interrupted tasks: {:?}", &ids);
+                            interrupted_ids = ids;
+                        }
+                        Err(e) => {
+                            error!("reset_interrupted_tasks failed: {}", e.to_string());
+                        }
+                    }
                     // 获取任务列表存入全局变量 TASKS
                     let db_res = get_all_download_tasks();
                     match db_res {
@@ -164,22 +306,48 @@ pub fn run() {
                 let mut app_lock = APP_HANDLE.write().unwrap();
                 *app_lock = Some(app_handle.clone());
             }
+            // 启动下载管理器
+            init_download_manager();
+            // 定时自动备份
+            init_backup_scheduler();
+            // 定时清理缓存
+            init_cache_manager();
+            // 删除旧版本按分页保存的作者缓存
+            tauri::async_runtime::spawn_blocking(remove_old_author_caches);
 
             {
-                let home_dir = home::home_dir().unwrap();
-                let setting_path = home_dir.join(format!(".comic_dl_tauri/setting.json"));
+                let setting_path = app_paths().setting_file();
                 let res =
                     read_from_json::<Setting>(&setting_path.to_str().unwrap()).unwrap_or(Setting {
-                        download_dir: String::from((&setting_path).to_str().unwrap_or("")),
+                        download_dir: app_paths()
+                            .default_download_dir()
+                            .to_string_lossy()
+                            .to_string(),
                         concurrent_task: String::from("1"),
                         concurrent_img: String::from("10"),
                         img_timeout: String::from("5"),
                         img_retry_count: String::from("3"),
+                        auto_resume: String::from("false"),
+                        auto_concurrent_img: String::from("false"),
+                        min_free_space: String::from("1024"),
+                        name_template: String::from(""),
+                        filename_rules: String::from("windows"),
+                        backup_interval: String::from("0"),
+                        backup_keep: String::from("7"),
+                        cache_max_size: String::from("500"),
+                        cache_ttl: String::from("30"),
+                        schedule: Vec::new(),
                     });
                 info!("SETTING: {:?}", res);
+                set_img_capacity(res.concurrent_img.parse::<usize>().unwrap_or(10));
                 let mut setting_lock = SETTING.write().unwrap();
                 *setting_lock = res;
             }
+            // 根据设置继续上次中断的下载
+            let auto_resume = SETTING.read().unwrap().auto_resume == "true";
+            if auto_resume && !interrupted_ids.is_empty() {
+                enqueue_tasks(&interrupted_ids);
+            }
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -193,44 +361,83 @@ pub fn run() {
             open_about_winfow,
             download_dir,
             add_new_task,
+            refresh_task,
+            preview_new_task,
             delete_tasks,
             start_or_pause,
             start_all,
             delete_all,
             pause_all,
             pause_all_waiting,
+            retry_failed,
+            retry_all_failed,
+            get_task_chapters,
+            set_task_weight,
+            move_task,
+            history_events,
+            history_totals,
+            history_sites,
+            history_hosts,
+            rename_library,
+            move_library,
+            open_task_dir,
+            import_folder,
+            backup_file_dialog,
+            get_backup_dir,
+            backup,
+            restore,
+            get_cache_stats,
+            clean_cache,
+            get_authors,
+            refresh_author,
+            add_author_comics,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // 退出前取消正在下载的任务，等待保存进度
+                cancel_all_tasks(Duration::from_secs(3));
+            }
+        });
 }
 
-async fn download_single_image(
-    id: i32,
-    group_index: usize,
-    index: usize,
-    url: String,
-    save_path: String,
-    permit: OwnedSemaphorePermit,
-    progress: Arc<AtomicUsize>,
-    total: i32,
-) -> DownloadResult {
-    let current_status = {
-        let temp = if let Ok(tasks) = TASKS.try_read() {
-            let target = tasks.iter().find(|x| x.id == id).unwrap();
-            target.status.clone()
-        } else {
-            String::from("")
-        };
-        temp
-    };
-    if current_status == "stopped" {
-        return DownloadResult {
-            group_index,
-            index,
-            error_msg: String::from("stopped"),
-            save_path,
-        };
+// 超时、连接失败或连接被重置说明请求过多
+fn request_outcome(e: &reqwest::Error) -> Outcome {
+    let mut source: Option<&dyn std::error::Error> = Some(e);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            if io_err.kind() == std::io::ErrorKind::ConnectionReset {
+                return Outcome::Throttled;
+            }
+        }
+        source = err.source();
+    }
+    if e.is_timeout() || e.is_connect() {
+        Outcome::Throttled
+    } else {
+        Outcome::Failed
+    }
+}
+
+// 分块读取图片内容，超出下载时间段的带宽限制时等待
+async fn read_body(id: i32, mut response: reqwest::Response) -> reqwest::Result<Bytes> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.chunk().await? {
+        record_bytes(id, chunk.len());
+        consume_bandwidth(chunk.len()).await;
+        body.extend_from_slice(&chunk);
     }
+    Ok(body.freeze())
+}
+
+// 请求单张图片，失败后按设置的次数重试，全部失败返回空 Bytes
+async fn fetch_img_bytes(
+    id: i32,
+    url: &str,
+    save_path: &str,
+    token: &CancellationToken,
+) -> (Bytes, u64) {
     let mut count = 0;
     let mut res;
     let img_setting = {
@@ -240,68 +447,146 @@ async fn download_single_image(
             res.img_retry_count.clone().parse::<u64>().unwrap_or(3),
         ]
     };
+    let host = get_host(url);
 
     loop {
+        if token.is_cancelled() {
+            res = Bytes::from("");
+            break;
+        }
         info!("download img loop count: {}", count);
         count += 1;
-        let response_result = timeout(
-            Duration::from_secs(img_setting[0]),
-            reqwest::get(url.clone()),
-        )
-        .await;
+        // 自动并发模式下等待图片域名的并发位置
+        let host_permit = tokio::select! {
+            _ = token.cancelled() => {
+                res = Bytes::from("");
+                break;
+            }
+            p = acquire_host(id, url) => p,
+        };
+        let start = Instant::now();
+        let response_result = tokio::select! {
+            _ = token.cancelled() => {
+                res = Bytes::from("");
+                break;
+            }
+            r = timeout(Duration::from_secs(img_setting[0]), reqwest::get(url)) => r,
+        };
 
         match response_result {
             Ok(Ok(response)) => {
                 if response.status().is_success() {
-                    let res_temp = response.bytes().await;
+                    let res_temp = tokio::select! {
+                        _ = token.cancelled() => {
+                            res = Bytes::from("");
+                            break;
+                        }
+                        r = read_body(id, response) => r,
+                    };
 
                     match res_temp {
                         Ok(bytes) => {
+                            report(&host_permit, Outcome::Success(start.elapsed()));
                             res = bytes;
                         }
                         Err(_e) => {
+                            report(&host_permit, request_outcome(&_e));
                             res = Bytes::from("");
                             error!(
                                 "download_single_image res id: {} save_path: {} error: {}",
-                                id, &save_path, _e
+                                id, save_path, _e
                             );
                         }
                     }
 
+                    record_request(id, &host, !res.is_empty(), start.elapsed(), res.len());
                     break;
                 } else {
+                    let status_code = response.status();
+                    if status_code == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status_code == reqwest::StatusCode::SERVICE_UNAVAILABLE
+                    {
+                        report(&host_permit, Outcome::Throttled);
+                    } else {
+                        report(&host_permit, Outcome::Failed);
+                    }
                     error!(
-                        "download_single_image response status failed id: {} save_path: {}",
-                        id, &save_path
+                        "download_single_image response status failed id: {} save_path: {} status: {}",
+                        id, save_path, status_code
                     );
                     res = Bytes::from("");
                 }
             }
             Ok(Err(_e)) => {
+                report(&host_permit, request_outcome(&_e));
                 error!(
                     "download_single_image id: {} save_path: {} err: {}",
-                    id, &save_path, _e
+                    id, save_path, _e
                 );
                 res = Bytes::from("");
             }
             Err(e) => {
+                report(&host_permit, Outcome::Throttled);
                 error!(
                     "download_single_image id: {} save_path: {} err: {}",
-                    id, &save_path, e
+                    id, save_path, e
                 );
                 res = Bytes::from("");
             }
         }
+        record_request(id, &host, false, start.elapsed(), 0);
 
         if count > img_setting[1] {
             break;
         }
     }
 
+    (res, count)
+}
+
+async fn download_single_image(
+    id: i32,
+    group_index: usize,
+    index: usize,
+    url: String,
+    alt_url: Option<String>,
+    save_path: String,
+    slot: ImgSlot,
+    progress: Arc<AtomicUsize>,
+    token: CancellationToken,
+) -> DownloadResult {
+    let stopped = |url: String, save_path: String| DownloadResult {
+        group_index,
+        index,
+        error_msg: String::from("stopped"),
+        href: url,
+        save_path,
+    };
+    if token.is_cancelled() {
+        return stopped(url, save_path);
+    }
+
+    let (mut res, mut count) = fetch_img_bytes(id, &url, &save_path, &token).await;
+    // 主地址失败时尝试备用地址
+    if res.is_empty() && !token.is_cancelled() {
+        if let Some(alt) = &alt_url {
+            info!(
+                "download img try alt url id: {} save_path: {} alt: {}",
+                id, &save_path, alt
+            );
+            let (alt_res, alt_count) = fetch_img_bytes(id, alt, &save_path, &token).await;
+            res = alt_res;
+            count += alt_count;
+        }
+    }
+
     info!(
         "download_single_image count: {} save_path: {}",
         count, &save_path
     );
+    if token.is_cancelled() {
+        return stopped(url, save_path);
+    }
 
     let mut error_msg = String::from("");
     if res.is_empty() {
@@ -339,51 +624,194 @@ async fn download_single_image(
         if error_msg.is_empty() {
             info!("download img emit progress save_path: {}", &save_path);
             progress.fetch_add(1, Ordering::Relaxed);
-            let pro = progress.load(Ordering::Relaxed);
-            if pro % 10 == 0 {
-                let app_lock = APP_HANDLE.read().unwrap().clone();
-
-                let progress_str = format!("{:.2}", ((pro as f32) / (total as f32) * 100.00));
-                let current_status = {
-                    let temp = if let Ok(tasks) = TASKS.try_read() {
-                        let target = tasks.iter().find(|x| x.id == id).unwrap();
-                        target.status.clone()
-                    } else {
-                        String::from("downloading")
-                    };
-                    temp
-                };
-
-                if let Some(app) = app_lock {
-                    let _ = &app
-                        .emit(
-                            "progress",
-                            DownloadEvent {
-                                id: id,
-                                progress: progress_str.clone(),
-                                count: total,
-                                now_count: pro as i32,
-                                error_vec: String::from(""),
-                                status: current_status,
-                            },
-                        )
-                        .unwrap();
-                }
-            }
+            record_img_done(id);
         }
     }
 
-    info!("download img drop permit save_path: {}", &save_path);
-    drop(permit);
+    info!("download img drop slot save_path: {}", &save_path);
+    drop(slot);
 
     DownloadResult {
         group_index,
         index,
         error_msg,
+        href: url,
         save_path,
     }
 }
 
+// 同步下载进度到 TASKS 和数据库，和发送进度一样每 PROGRESS_INTERVAL 最多一次，结束时另外保存最后的进度
+fn sync_progress(id: i32, current_progress: usize, total: i32, last_sync: &mut Instant) {
+    if last_sync.elapsed() < PROGRESS_INTERVAL {
+        return;
+    }
+    *last_sync = Instant::now();
+    let progress_str = format!(
+        "{:.2}",
+        ((current_progress as f32) / (total as f32) * 100.00)
+    );
+    {
+        let mut tasks = TASKS.write().unwrap();
+        if let Some(temp) = tasks.iter_mut().find(|x| x.id == id) {
+            temp.progress = progress_str.clone();
+            temp.now_count = current_progress as i32;
+        }
+    }
+    if let Err(e) = update_download_task_progress(id, &progress_str, current_progress as i32) {
+        error!("save progress to db failed: {} e: {}", id, e);
+    }
+}
+
+// 任务被取消时保留用户设置的状态（暂停、重新排队或退出程序），任务已被删除时返回 None
+// 下载时间段结束或磁盘空间不足时任务已放回等待队列，即使有失败的图片也保持 waiting 以便自动继续
+fn final_task_status(id: i32, token: &CancellationToken, status: &str) -> Option<String> {
+    let current = TASKS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|x| x.id == id)
+        .map(|x| x.status.clone())?;
+    if token.is_cancelled()
+        && (status == "stopped" || (current == "waiting" && status != "finished"))
+    {
+        Some(current)
+    } else {
+        Some(status.to_string())
+    }
+}
+
+// 追加一条没有下载统计的任务历史，如 started deleted
+pub(crate) fn save_task_event(task: &PartialDownloadTask, event: &str) {
+    let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let site = get_second_level_domain(&task.url).unwrap_or_default();
+    let history = NewTaskHistory {
+        task_id: task.id,
+        event,
+        comic_name: &task.comic_name,
+        author: &task.author,
+        dl_type: &task.dl_type,
+        site: &site,
+        bytes: 0,
+        duration_ms: 0,
+        img_count: 0,
+        error_count: 0,
+        created_at: &now,
+    };
+    if let Err(e) = insert_task_history(&history, &vec![]) {
+        error!("save_task_event failed: {} e: {}", task.id, e);
+    }
+}
+
+// 一次下载结束时记录字节数、耗时、失败数和各图片域名的统计
+fn save_run_history(task: &DownloadTask, status: &str, error_count: usize) {
+    let event = match status {
+        "finished" | "failed" => status,
+        _ => "paused",
+    };
+    let run = run_stats(task.id);
+    let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let site = get_second_level_domain(&task.url).unwrap_or_default();
+    let history = NewTaskHistory {
+        task_id: task.id,
+        event,
+        comic_name: &task.comic_name,
+        author: &task.author,
+        dl_type: &task.dl_type,
+        site: &site,
+        bytes: run.bytes as i64,
+        duration_ms: run.duration.as_millis() as i64,
+        img_count: run.downloaded as i32,
+        error_count: error_count as i32,
+        created_at: &now,
+    };
+    let hosts: Vec<NewHostHistory> = run
+        .hosts
+        .iter()
+        .map(|(host, counter)| NewHostHistory {
+            task_id: task.id,
+            host,
+            requests: counter.requests as i32,
+            failures: counter.failures as i32,
+            bytes: counter.bytes as i64,
+            duration_ms: counter.duration.as_millis() as i64,
+            created_at: &now,
+        })
+        .collect();
+    if let Err(e) = insert_task_history(&history, &hosts) {
+        error!("save_run_history failed: {} e: {}", task.id, e);
+    }
+}
+
+fn take_retry_selection(id: i32) -> Option<HashSet<(usize, usize)>> {
+    RETRY_SELECTION.write().unwrap().remove(&id)
+}
+
+// 只重试失败图片的任务，重试后 pages 表中没有失败的图片时任务完成
+fn retry_finished(
+    id: i32,
+    retry_only: &Option<HashSet<(usize, usize)>>,
+    token: &CancellationToken,
+) -> bool {
+    retry_only.is_some()
+        && !token.is_cancelled()
+        && get_failed_pages(id).is_ok_and(|failed| failed.is_empty())
+}
+
+// 根据 pages 表中记录的失败图片计算需要重试的图片，没有失败记录时返回 None 表示重试全部未完成图片
+fn failed_selection(id: i32) -> Option<HashSet<(usize, usize)>> {
+    let failed = get_failed_pages(id).ok()?;
+    if failed.is_empty() {
+        return None;
+    }
+    Some(
+        failed
+            .into_iter()
+            .map(|(group_index, index)| (group_index as usize, index as usize))
+            .collect(),
+    )
+}
+
+// 每张图片下载完成后更新 pages 表
+fn save_page_result(id: i32, result: &DownloadResult) {
+    if let Err(e) = update_page_result(
+        id,
+        result.group_index as i32,
+        result.index as i32,
+        result.error_msg.is_empty(),
+        &result.error_msg,
+        if result.error_msg.is_empty() {
+            &result.save_path
+        } else {
+            ""
+        },
+    ) {
+        error!(
+            "save page result failed: id: {} group_index: {} index: {} e: {}",
+            id, result.group_index, result.index, e
+        );
+    }
+}
+
+// 任务停止时剩下的图片，已经下载完成的保存结果，避免下次重新下载，没有完成的取消
+async fn drain_finished(
+    id: i32,
+    tasks: &mut [tokio::task::JoinHandle<DownloadResult>],
+    all_results: &mut Vec<DownloadResult>,
+) {
+    for task in tasks.iter_mut() {
+        if !task.is_finished() {
+            task.abort();
+            continue;
+        }
+        if let Ok(result) = task.await {
+            if result.error_msg != "stopped" {
+                save_page_result(id, &result);
+                all_results.push(result);
+            }
+        }
+    }
+}
+
 fn sort_tasks() {
     const STATUS_ORDER: [&str; 5] = ["downloading", "waiting", "stopped", "failed", "finished"];
     let mut tasks = TASKS.write().unwrap();
@@ -417,108 +845,78 @@ fn sort_tasks() {
         //     return now_count_cmp;
         // }
 
-        // 如果 now_count 也相同，再按 count 升序排序
-        a.count.cmp(&b.count)
+        // 状态相同时按队列顺序
+        a.queue_position.cmp(&b.queue_position)
     });
 }
 
-fn get_downloading_count() -> GetDownloadingCount {
-    let tasks = TASKS.read().unwrap();
-    let mut count = 0;
-    let mut downloading_ids: Vec<i32> = Vec::new();
-    for task in tasks.iter() {
-        if task.status == "downloading" {
-            count += 1;
-            downloading_ids.push(task.id);
+async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask, token: CancellationToken) {
+    let all_count = complete_current_task.count;
+    let cache_json: Vec<CurrentElement> = match get_task_groups(complete_current_task.id) {
+        Ok(groups) => groups,
+        Err(e) => {
+            error!(
+                "run_join_set_juanhuafanwai get_task_groups failed: {} e: {}",
+                complete_current_task.id, e
+            );
+            Vec::new()
         }
-    }
-    GetDownloadingCount {
-        count,
-        downloading_ids,
-    }
-}
-
-fn start_waiting(app: &AppHandle) {
-    let current_downloading = get_downloading_count();
-    let concurrent_count = {
-        let res = SETTING.read().unwrap();
-        res.concurrent_task.clone().parse::<i32>().unwrap_or(1)
     };
-    if current_downloading.count < concurrent_count {
-        // 改变第一个 waiting 的 task
-        // let mut tasks = TASKS.write().unwrap();
-        // if let Some(task) = tasks.iter_mut().find(|t| t.status == "waiting") {
-        //     task.status = "downloading".to_string();
-        // }
-        let change_count = concurrent_count - current_downloading.count;
-        let mut modified_count = 0;
-        let tasks = TASKS.write().unwrap();
-        let mut changed_vec: Vec<i32> = Vec::new();
-        for task in tasks.iter() {
-            if task.status == "waiting" {
-                info!("!!!!!!!!!! will start {:?}", &task);
-                changed_vec.push(task.id);
-                // task.status = "downloading".to_string();
-                modified_count += 1;
-                if modified_count == change_count {
-                    break;
-                }
-            }
-        }
-        app.emit("start_waiting", changed_vec).unwrap();
-    }
-}
-
-async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
-    let all_count = complete_current_task.count;
-    let cache_json_str = &complete_current_task.cache_json;
-    let cache_json: Vec<CurrentElement> = serde_json::from_str(&cache_json_str).unwrap();
     let total = all_count;
     let progress = Arc::new(AtomicUsize::new(0));
+    start_task_stats(complete_current_task.id, total, progress.clone());
+    let mut last_sync = Instant::now();
 
-    let comic_type = match complete_current_task.dl_type.as_str() {
-        "juan" => String::from("单行本"),
-        "hua" => String::from("单话"),
-        "fanwai" => String::from("番外篇"),
-        "current" => String::from("current"),
-        _ => String::from(""),
-    };
+    let comic_type = comic_type(&complete_current_task);
+    let template = task_template(&complete_current_task.dl_type);
     let comic_basic_path = {
         let res = SETTING.read().unwrap();
         PathBuf::from(res.download_dir.clone())
     };
 
+    // 处理后重名的章节目录加后缀
+    let rules = name_rules();
+    let chapter_dirs = chapter_dirs(&cache_json.iter().map(|x| x.name.clone()).collect(), rules);
+
     let mut all_results = Vec::new();
-    let mut cache_json_sync = cache_json.clone();
+    let retry_only = take_retry_selection(complete_current_task.id);
 
     'outer: for (group_index, url_group) in cache_json.into_iter().enumerate() {
-        let concurrent_count = {
-            let res = SETTING.read().unwrap();
-            res.concurrent_img.clone().parse::<usize>().unwrap_or(10)
-        };
-        let semaphore = Arc::new(Semaphore::new(concurrent_count));
+        if token.is_cancelled() {
+            break;
+        }
         let mut tasks = Vec::new();
 
         for (i, url) in url_group.imgs.into_iter().enumerate() {
+            if token.is_cancelled() {
+                break;
+            }
             if url.done {
                 progress.fetch_add(1, Ordering::Relaxed);
                 continue;
             }
+            if let Some(only) = &retry_only {
+                if !only.contains(&(group_index, i)) {
+                    continue;
+                }
+            }
 
-            let save_path_temp = if complete_current_task.author.is_empty() {
-                comic_basic_path.join(format!(
-                    "{}_{}/{}/{}.jpg",
-                    complete_current_task.comic_name, &comic_type, url_group.name, i,
-                ))
-            } else {
-                comic_basic_path.join(format!(
-                    "{}/{}_{}/{}/{}.jpg",
-                    complete_current_task.author,
-                    complete_current_task.comic_name,
-                    &comic_type,
-                    url_group.name,
-                    i,
-                ))
+            let save_path_temp = match page_file(
+                &complete_current_task,
+                &template,
+                group_index,
+                &chapter_dirs[group_index],
+                i,
+                Some(rules),
+            ) {
+                Ok(path) => comic_basic_path.join(path),
+                Err(e) => {
+                    error!(
+                        "run_join_set_juanhuafanwai page_file failed: {} e: {}",
+                        complete_current_task.id, e
+                    );
+                    break 'outer;
+                }
             };
             let parent_path = save_path_temp.parent().unwrap();
             if !parent_path.exists() {
@@ -527,22 +925,27 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
             let save_path = save_path_temp.to_str().unwrap().to_string().clone();
             let url_str = url.href.clone();
             let process_clone = progress.clone();
-            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            // 等待所有任务共用的图片下载位置
+            let slot = match acquire_img_slot(complete_current_task.id, &token).await {
+                Some(slot) => slot,
+                None => break,
+            };
             let task = tokio::task::spawn(download_single_image(
                 complete_current_task.id,
                 group_index,
                 i,
                 url_str,
+                url.alt_href.clone(),
                 save_path,
-                permit,
+                slot,
                 process_clone,
-                total,
+                token.clone(),
             ));
             tasks.push(task);
         }
 
-        for (_index, task) in tasks.iter_mut().enumerate() {
-            match task.await {
+        for index in 0..tasks.len() {
+            match (&mut tasks[index]).await {
                 Ok(result) => {
                     info!(
                         "run_join_set_juanhuafanwai join_set.join_next group_index: {} index: {} save_path: {} error_msg: {}",
@@ -552,18 +955,20 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
                         result.error_msg,
                     );
                     if result.error_msg == "stopped" {
-                        // 取消其他任务
-                        for (_index, t) in tasks.iter_mut().enumerate() {
-                            t.abort();
-                        }
+                        // 保存已经完成的图片，取消其他任务
+                        drain_finished(
+                            complete_current_task.id,
+                            &mut tasks[index + 1..],
+                            &mut all_results,
+                        )
+                        .await;
                         break 'outer;
                     }
                     all_results.push(result.clone());
                     if !&result.error_msg.is_empty() {
                         error!("Task error: {}", &result.error_msg);
-                    } else {
-                        cache_json_sync[result.group_index].imgs[result.index].done = true;
                     }
+                    save_page_result(complete_current_task.id, &result);
                 }
                 Err(_) => {
                     error!("Task join error");
@@ -575,40 +980,12 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
                 }
             }
 
-            let current_progress = progress.load(Ordering::Relaxed);
-            if current_progress % 10 == 0 {
-                let progress_str = format!(
-                    "{:.2}",
-                    ((current_progress as f32) / (total as f32) * 100.00)
-                );
-
-                {
-                    if let Ok(mut tasks) = TASKS.try_write() {
-                        if let Some(temp) =
-                            tasks.iter_mut().find(|x| x.id == complete_current_task.id)
-                        {
-                            temp.progress = progress_str.clone();
-                            temp.now_count = current_progress as i32;
-                        }
-                    } else {
-                        error!("Failed to sync TASKS");
-                    }
-                }
-            }
-            if current_progress % 30 == 0 {
-                let progress_str = format!(
-                    "{:.2}",
-                    ((current_progress as f32) / (total as f32) * 100.00)
-                );
-                if let Err(e) = update_download_task_progress(
-                    complete_current_task.id,
-                    &progress_str,
-                    current_progress as i32,
-                    &serde_json::to_string_pretty(&cache_json_sync).unwrap(),
-                ) {
-                    error!("save progress to db failed: {}", e);
-                }
-            }
+            sync_progress(
+                complete_current_task.id,
+                progress.load(Ordering::Relaxed),
+                total,
+                &mut last_sync,
+            );
             info!("run_join_set_juanhuafanwai task.await finished");
         }
     }
@@ -621,10 +998,16 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
     );
 
     // 可以在这里进一步处理所有的下载结果 all_results
-    let mut error_vec: Vec<String> = Vec::new();
+    let mut error_vec: Vec<FailedImg> = Vec::new();
     for result in all_results {
         if !result.error_msg.is_empty() && result.error_msg != "stopped" {
-            error_vec.push(result.error_msg);
+            error_vec.push(FailedImg {
+                group_index: result.group_index,
+                index: result.index,
+                href: result.href,
+                save_path: result.save_path,
+                error_msg: result.error_msg,
+            });
         }
     }
 
@@ -633,13 +1016,26 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
         error!("Final result error: {:?}", &error_vec);
 
         status_for_db = "failed";
+    } else if current_progress as i32 == total
+        || retry_finished(complete_current_task.id, &retry_only, &token)
+    {
+        status_for_db = "finished";
     } else {
-        status_for_db = if current_progress as i32 == total {
-            "finished"
-        } else {
-            "stopped"
-        };
+        status_for_db = "stopped";
     }
+    // 重试后没有剩余的失败图片时进度为 100%
+    let (current_progress, progress_str) = if status_for_db == "finished" {
+        (total as usize, String::from("100.00"))
+    } else {
+        (current_progress, progress_str)
+    };
+    let status_for_db = match final_task_status(complete_current_task.id, &token, status_for_db) {
+        Some(status) => status,
+        None => {
+            info!("task deleted, skip saving: {}", complete_current_task.id);
+            return;
+        }
+    };
     let app_lock = APP_HANDLE.read().unwrap().clone();
     if let Some(app) = app_lock {
         let _ = &app
@@ -652,6 +1048,10 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
                     now_count: current_progress as i32,
                     error_vec: serde_json::to_string_pretty(&error_vec).unwrap(),
                     status: status_for_db.to_string(),
+                    img_concurrency: task_img_concurrency(complete_current_task.id),
+                    speed: 0,
+                    bytes: task_bytes(complete_current_task.id),
+                    eta: if status_for_db == "finished" { 0 } else { -1 },
                 },
             )
             .unwrap();
@@ -660,12 +1060,12 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
         complete_current_task.id,
         &progress_str,
         current_progress as i32,
-        &serde_json::to_string_pretty(&cache_json_sync).unwrap(),
         serde_json::to_string_pretty(&error_vec).unwrap().as_str(),
-        status_for_db,
+        &status_for_db,
     ) {
         error!("save error_msg to db failed: {}", e);
     }
+    save_run_history(&complete_current_task, &status_for_db, error_vec.len());
     {
         let mut tasks = TASKS.write().unwrap();
         if let Some(temp) = tasks.iter_mut().find(|x| x.id == complete_current_task.id) {
@@ -702,33 +1102,69 @@ async fn run_join_set_juanhuafanwai(complete_current_task: DownloadTask) {
     sort_tasks();
 }
 
-async fn run_join_set_current(complete_current_task: DownloadTask) {
+async fn run_join_set_current(complete_current_task: DownloadTask, token: CancellationToken) {
     let all_count = complete_current_task.count;
-    let cache_json_str = &complete_current_task.cache_json;
-    let cache_json: Vec<Img> = serde_json::from_str(&cache_json_str).unwrap();
-    let concurrent_count = {
-        let res = SETTING.read().unwrap();
-        res.concurrent_img.clone().parse::<usize>().unwrap_or(10)
-    };
-    let semaphore = Arc::new(Semaphore::new(concurrent_count));
+    let (chapter_name, cache_json): (String, Vec<Img>) =
+        match get_task_groups(complete_current_task.id) {
+            Ok(groups) => groups
+                .into_iter()
+                .next()
+                .map(|x| (x.name, x.imgs))
+                .unwrap_or_default(),
+            Err(e) => {
+                error!(
+                    "run_join_set_current get_task_groups failed: {} e: {}",
+                    complete_current_task.id, e
+                );
+                (String::new(), Vec::new())
+            }
+        };
     let total = all_count;
     let progress = Arc::new(AtomicUsize::new(0));
+    start_task_stats(complete_current_task.id, total, progress.clone());
+    let mut last_sync = Instant::now();
     let mut all_results = Vec::new();
-    let mut cache_json_sync = cache_json.clone();
     let mut tasks = Vec::new();
 
+    let template = task_template(&complete_current_task.dl_type);
+    let rules = name_rules();
     let comic_basic_path = {
         let res = SETTING.read().unwrap();
         PathBuf::from(res.download_dir.clone())
     };
 
+    let retry_only = take_retry_selection(complete_current_task.id);
+
     for (i, url) in cache_json.into_iter().enumerate() {
+        if token.is_cancelled() {
+            break;
+        }
         if url.done {
             progress.fetch_add(1, Ordering::Relaxed);
             continue;
         }
-        let save_path_temp =
-            comic_basic_path.join(format!("{}/{}.jpg", complete_current_task.comic_name, i,));
+        if let Some(only) = &retry_only {
+            if !only.contains(&(0, i)) {
+                continue;
+            }
+        }
+        let save_path_temp = match page_file(
+            &complete_current_task,
+            &template,
+            0,
+            &chapter_name,
+            i,
+            Some(rules),
+        ) {
+            Ok(path) => comic_basic_path.join(path),
+            Err(e) => {
+                error!(
+                    "run_join_set_current page_file failed: {} e: {}",
+                    complete_current_task.id, e
+                );
+                break;
+            }
+        };
         let parent_path = save_path_temp.parent().unwrap();
         if !parent_path.exists() {
             fs::create_dir_all(parent_path).unwrap();
@@ -736,38 +1172,44 @@ async fn run_join_set_current(complete_current_task: DownloadTask) {
         let save_path = save_path_temp.to_str().unwrap().to_string().clone();
         let url_str = url.href.clone();
 
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        // 等待所有任务共用的图片下载位置
+        let slot = match acquire_img_slot(complete_current_task.id, &token).await {
+            Some(slot) => slot,
+            None => break,
+        };
         let progress_clone = progress.clone();
         let task = tokio::task::spawn(download_single_image(
             complete_current_task.id,
             0,
             i,
             url_str,
+            url.alt_href.clone(),
             save_path,
-            permit,
+            slot,
             progress_clone,
-            total,
+            token.clone(),
         ));
         tasks.push(task);
     }
 
-    for (index, task) in tasks.iter_mut().enumerate() {
-        match task.await {
+    for index in 0..tasks.len() {
+        match (&mut tasks[index]).await {
             Ok(result) => {
                 if result.error_msg == "stopped" {
-                    for (i, t) in tasks.iter_mut().enumerate() {
-                        if i != index {
-                            t.abort();
-                        }
-                    }
+                    // 保存已经完成的图片，取消其他任务
+                    drain_finished(
+                        complete_current_task.id,
+                        &mut tasks[index + 1..],
+                        &mut all_results,
+                    )
+                    .await;
                     break;
                 }
                 all_results.push(result.clone());
                 if !&result.error_msg.is_empty() {
                     error!("current Task error: {}", &result.error_msg);
-                } else {
-                    cache_json_sync[result.index].done = true;
                 }
+                save_page_result(complete_current_task.id, &result);
             }
             Err(_) => {
                 error!("current Task join error");
@@ -778,41 +1220,13 @@ async fn run_join_set_current(complete_current_task: DownloadTask) {
             }
         }
 
-        // 保存进度到数据库
-        let current_progress = progress.load(Ordering::Relaxed);
-        if current_progress % 10 == 0 {
-            let progress_str = format!(
-                "{:.2}",
-                ((current_progress as f32) / (total as f32) * 100.00)
-            );
-
-            {
-                if let Ok(mut tasks) = TASKS.try_write() {
-                    if let Some(temp) = tasks.iter_mut().find(|x| x.id == complete_current_task.id)
-                    {
-                        temp.progress = progress_str;
-                        temp.now_count = current_progress as i32;
-                    }
-                } else {
-                    error!("Failed to sync TASKS");
-                }
-            }
-        }
-        if current_progress % 30 == 0 {
-            let progress_str = format!(
-                "{:.2}",
-                ((current_progress as f32) / (total as f32) * 100.00)
-            );
-            if let Err(e) = update_download_task_progress(
-                complete_current_task.id,
-                &progress_str,
-                current_progress as i32,
-                &serde_json::to_string_pretty(&cache_json_sync).unwrap(),
-            ) {
-                error!("save current progress to db failed: {}", e);
-            }
-        }
-    }
+        sync_progress(
+            complete_current_task.id,
+            progress.load(Ordering::Relaxed),
+            total,
+            &mut last_sync,
+        );
+    }
 
     // 确保最后一次进度也保存到数据库
     let current_progress = progress.load(Ordering::Relaxed);
@@ -822,10 +1236,16 @@ async fn run_join_set_current(complete_current_task: DownloadTask) {
     );
 
     // 可以在这里进一步处理所有的下载结果 all_results
-    let mut error_vec: Vec<String> = Vec::new();
+    let mut error_vec: Vec<FailedImg> = Vec::new();
     for result in all_results {
         if !result.error_msg.is_empty() && result.error_msg != "stopped" {
-            error_vec.push(result.error_msg);
+            error_vec.push(FailedImg {
+                group_index: result.group_index,
+                index: result.index,
+                href: result.href,
+                save_path: result.save_path,
+                error_msg: result.error_msg,
+            });
         }
     }
 
@@ -833,13 +1253,26 @@ async fn run_join_set_current(complete_current_task: DownloadTask) {
     if !error_vec.is_empty() {
         error!("current Final result error: {:?}", &error_vec);
         status_for_db = "failed";
+    } else if current_progress as i32 == total
+        || retry_finished(complete_current_task.id, &retry_only, &token)
+    {
+        status_for_db = "finished";
     } else {
-        status_for_db = if current_progress as i32 == total {
-            "finished"
-        } else {
-            "stopped"
-        };
+        status_for_db = "stopped";
     }
+    // 重试后没有剩余的失败图片时进度为 100%
+    let (current_progress, progress_str) = if status_for_db == "finished" {
+        (total as usize, String::from("100.00"))
+    } else {
+        (current_progress, progress_str)
+    };
+    let status_for_db = match final_task_status(complete_current_task.id, &token, status_for_db) {
+        Some(status) => status,
+        None => {
+            info!("task deleted, skip saving: {}", complete_current_task.id);
+            return;
+        }
+    };
     let app_lock = APP_HANDLE.read().unwrap().clone();
     if let Some(app) = app_lock {
         let _ = &app
@@ -852,6 +1285,10 @@ async fn run_join_set_current(complete_current_task: DownloadTask) {
                     now_count: current_progress as i32,
                     error_vec: serde_json::to_string_pretty(&error_vec).unwrap(),
                     status: status_for_db.to_string(),
+                    img_concurrency: task_img_concurrency(complete_current_task.id),
+                    speed: 0,
+                    bytes: task_bytes(complete_current_task.id),
+                    eta: if status_for_db == "finished" { 0 } else { -1 },
                 },
             )
             .unwrap();
@@ -861,14 +1298,14 @@ async fn run_join_set_current(complete_current_task: DownloadTask) {
         complete_current_task.id,
         &progress_str,
         current_progress as i32,
-        &serde_json::to_string_pretty(&cache_json_sync).unwrap(),
         serde_json::to_string_pretty(&error_vec).unwrap().as_str(),
-        status_for_db,
+        &status_for_db,
     ) {
         error!("save error_msg to db failed: {}", e);
     }
+    save_run_history(&complete_current_task, &status_for_db, error_vec.len());
     {
-        let mut tasks = TASKS.try_write().unwrap();
+        let mut tasks = TASKS.write().unwrap();
         if let Some(temp) = tasks.iter_mut().find(|x| x.id == complete_current_task.id) {
             temp.error_vec = serde_json::to_string_pretty(&error_vec).unwrap();
             temp.status = status_for_db.to_string();
@@ -901,149 +1338,35 @@ async fn run_join_set_current(complete_current_task: DownloadTask) {
 
 #[tauri::command]
 async fn start_or_pause(app: AppHandle, id: i32, status: String) {
+    info!("start_or_pause status: {} id: {}", status, id);
     if status == "stopped" {
-        let mut tasks = TASKS.write().unwrap();
-        for task in tasks.iter_mut() {
-            if task.id == id {
-                task.status = String::from("stopped");
+        {
+            let mut tasks = TASKS.write().unwrap();
+            for task in tasks.iter_mut() {
+                if task.id == id {
+                    task.status = String::from("stopped");
+                    task.pause_reason = String::from("");
+                }
             }
         }
 
-        let _update_res = update_download_task_status(id, &status);
+        cancel_task(id);
+        let _update_res = update_download_task_status_reason(id, &status, "");
         let _ = &app
             .emit(
                 "task_status",
-                HashMap::from([("id", id.to_string()), ("status", String::from("stopped"))]),
+                HashMap::from([
+                    ("id", id.to_string()),
+                    ("status", String::from("stopped")),
+                    ("pause_reason", String::from("")),
+                ]),
             )
             .unwrap();
         return;
     }
 
-    let concurrent_count = {
-        let res = SETTING.read().unwrap();
-        res.concurrent_task.clone().parse::<i32>().unwrap_or(1)
-    };
-    let downloading_count = get_downloading_count();
-    if downloading_count.downloading_ids.contains(&id) {
-        info!("already downloading");
-        return;
-    }
-    info!(
-        "start_or_pause downloading_count: {} status: {} id: {}",
-        downloading_count.count, status, id
-    );
-    let final_status = if downloading_count.count >= concurrent_count && status == "downloading" {
-        String::from("waiting")
-    } else {
-        String::from("downloading")
-    };
-    let current_task = {
-        let mut tasks = TASKS.write().unwrap();
-        let mut current_task = None;
-        for task in tasks.iter_mut() {
-            if task.id == id {
-                task.status = final_status.clone();
-                current_task = Some(task.clone());
-            }
-        }
-        current_task
-    };
-
-    sort_tasks();
-
-    if let Some(current_task_temp) = current_task {
-        let update_res = update_download_task_status(id, &final_status);
-        if update_res.is_ok() {
-            info!("update task status success: {}", id);
-            let _ = &app
-                .emit(
-                    "task_status",
-                    HashMap::from([("id", id.to_string()), ("status", final_status.clone())]),
-                )
-                .unwrap();
-            if final_status == "downloading" {
-                let complete_current_task: DownloadTask = get_download_task(id).unwrap();
-                let complete_current_task_copy = complete_current_task.clone();
-                if current_task_temp.dl_type == "juan"
-                    || current_task_temp.dl_type == "hua"
-                    || current_task_temp.dl_type == "fanwai"
-                {
-                    let thread_error_msg = format!(
-                        "The child thread crashed: id: {} comic_name: {} dl_type: {}",
-                        &complete_current_task.id,
-                        &complete_current_task.comic_name,
-                        &complete_current_task.dl_type,
-                    );
-
-                    let result = std::thread::spawn(|| {
-                        let rt = Runtime::new().unwrap();
-
-                        rt.block_on(async {
-                            run_join_set_juanhuafanwai(complete_current_task).await
-                        });
-                    });
-
-                    if let Err(e) = result.join() {
-                        if let Some(panic_msg) = e.downcast_ref::<String>() {
-                            eprintln!("Thread panicked with message: {}", panic_msg);
-                        } else if let Some(panic_msg) = e.downcast_ref::<&str>() {
-                            eprintln!("Thread panicked with message: {}", panic_msg);
-                        } else {
-                            eprintln!("Thread panicked with an unknown error.");
-                        }
-                        error!("thread_error_msg: {} e: {:?}", &thread_error_msg, e);
-
-                        let app_lock = APP_HANDLE.read().unwrap().clone();
-                        if let Some(app) = app_lock {
-                            let _ = &app.emit("err_msg_main", &thread_error_msg).unwrap();
-                        }
-                    } else {
-                        info!("id: {} thread finished", id);
-                        let app_lock = APP_HANDLE.read().unwrap().clone();
-                        if let Some(app) = app_lock {
-                            start_waiting(&app);
-                        }
-                    }
-                } else if current_task_temp.dl_type == "current" {
-                    let thread_error_msg = format!(
-                        "The child thread crashed: id: {} comic_name: {} dl_type: {}",
-                        &current_task_temp.id,
-                        &current_task_temp.comic_name,
-                        &current_task_temp.dl_type,
-                    );
-
-                    let result = spawn(|| {
-                        let rt = Runtime::new().unwrap();
-
-                        let _result = rt.block_on(async {
-                            run_join_set_current(complete_current_task_copy).await
-                        });
-                    });
-                    if let Err(e) = result.join() {
-                        error!("thread_error_msg: {} e: {:?}", &thread_error_msg, e);
-                        let app_lock = APP_HANDLE.read().unwrap().clone();
-                        if let Some(app) = app_lock {
-                            let _ = &app.emit("err_msg_main", &thread_error_msg).unwrap();
-                        }
-                    } else {
-                        info!("id: {} thread finished", id);
-                        let app_lock = APP_HANDLE.read().unwrap().clone();
-                        if let Some(app) = app_lock {
-                            start_waiting(&app);
-                        }
-                    }
-                }
-            }
-        } else {
-            error!(
-                "update task status failed: {}, status: {}",
-                id, &final_status
-            );
-            let _ = &app
-                .emit("info_msg_main", "update task status failed")
-                .unwrap();
-        }
-    }
+    // 交给下载管理器排队，有空位时开始下载
+    enqueue_tasks(&vec![id]);
 }
 
 #[tauri::command]
@@ -1056,45 +1379,20 @@ async fn get_tasks(_app: AppHandle) -> Vec<PartialDownloadTask> {
 #[tauri::command]
 async fn start_all(_app: AppHandle) -> StartAllRes {
     info!("start_all ");
-    let mut tasks = TASKS.write().unwrap();
-    let mut count = 0;
-    let mut data_for_db: Vec<StartAllData> = Vec::new();
-    let concurrent_count = {
-        let res = SETTING.read().unwrap();
-        res.concurrent_task.clone().parse::<i32>().unwrap_or(1)
+    let ids: Vec<i32> = {
+        let tasks = TASKS.read().unwrap();
+        tasks
+            .iter()
+            .filter(|x| x.status == "stopped" || x.status == "failed")
+            .map(|x| x.id)
+            .collect()
     };
-    for task in tasks.iter_mut() {
-        if task.status == "downloading" {
-            count += 1;
-            continue;
-        } else if task.status == "stopped" || task.status == "failed" {
-            info!(
-                "start_all status: {} id: {} count: {}",
-                task.status, task.id, count
-            );
-            if count >= concurrent_count {
-                task.status = String::from("waiting");
-                data_for_db.push(StartAllData {
-                    id: task.id,
-                    status: String::from("waiting"),
-                });
-            } else {
-                // task.status = String::from("downloading");
-                data_for_db.push(StartAllData {
-                    id: task.id,
-                    status: String::from("downloading"),
-                });
-                count += 1;
-            }
-        }
-    }
-    info!("data_for_db: {:?}", &data_for_db);
-    update_batch_status(&data_for_db);
+    let changed = enqueue_tasks(&ids);
 
-    let tasks_res = tasks.clone();
+    let tasks_res = TASKS.read().unwrap().clone();
     StartAllRes {
         tasks: tasks_res,
-        changed: data_for_db,
+        changed,
     }
 }
 
@@ -1103,9 +1401,12 @@ async fn delete_all() -> Vec<PartialDownloadTask> {
     let mut tasks = TASKS.write().unwrap();
     let mut data_for_db: Vec<i32> = Vec::new();
     let mut new_tasks: Vec<PartialDownloadTask> = Vec::new();
+    let mut deleted: Vec<PartialDownloadTask> = Vec::new();
     for task in tasks.drain(..) {
         if task.status != "downloading" {
+            save_task_event(&task, "deleted");
             data_for_db.push(task.id);
+            deleted.push(task);
         } else {
             new_tasks.push(task);
         }
@@ -1113,17 +1414,31 @@ async fn delete_all() -> Vec<PartialDownloadTask> {
     *tasks = new_tasks;
 
     let _ = delete_batch_status_not_downloading(data_for_db);
+    clear_task_cache(&deleted, &tasks);
 
     tasks.clone()
 }
 
 #[tauri::command]
 async fn delete_tasks(_app: AppHandle, id: i32) -> isize {
+    // 正在下载的任务先取消，协程发现任务已删除后不再保存状态
+    cancel_task(id);
+    let task = TASKS.read().unwrap().iter().find(|x| x.id == id).cloned();
     let del_res = delete_download_task(id);
     match del_res {
         Ok(res) => {
-            let mut tasks = TASKS.write().unwrap();
-            tasks.retain(|x| x.id != id);
+            if let Some(task) = &task {
+                save_task_event(task, "deleted");
+            }
+            take_retry_selection(id);
+            let remaining = {
+                let mut tasks = TASKS.write().unwrap();
+                tasks.retain(|x| x.id != id);
+                tasks.clone()
+            };
+            if let Some(task) = &task {
+                clear_task_cache(std::slice::from_ref(task), &remaining);
+            }
             info!("delete task: {}", res);
             res as isize
         }
@@ -1143,6 +1458,7 @@ async fn pause_all(_app: AppHandle) -> Vec<PartialDownloadTask> {
                 for task in tasks.iter_mut() {
                     if task.status == "downloading" || task.status == "waiting" {
                         task.status = String::from("stopped");
+                        task.pause_reason = String::from("");
                         data_for_db.push(StartAllData {
                             id: task.id,
                             status: String::from("stopped"),
@@ -1150,13 +1466,18 @@ async fn pause_all(_app: AppHandle) -> Vec<PartialDownloadTask> {
                     }
                 }
                 update_batch_status(&data_for_db);
-                break tasks;
+                // 先释放 TASKS 再取消任务，和 schedule 一样先锁 RUNNING 再锁 TASKS
+                break tasks.clone();
             }
             Err(_e) => {}
         };
     };
 
-    tasks.clone()
+    // 取消正在下载的任务
+    for task in tasks.iter().filter(|x| x.status == "stopped") {
+        cancel_task(task.id);
+    }
+    tasks
 }
 
 #[tauri::command]
@@ -1168,6 +1489,7 @@ async fn pause_all_waiting() -> Vec<PartialDownloadTask> {
                 for task in tasks.iter_mut() {
                     if task.status == "waiting" {
                         task.status = String::from("stopped");
+                        task.pause_reason = String::from("");
                         data_for_db.push(StartAllData {
                             id: task.id,
                             status: String::from("stopped"),
@@ -1184,6 +1506,209 @@ async fn pause_all_waiting() -> Vec<PartialDownloadTask> {
     tasks.clone()
 }
 
+// 修改任务分配图片下载位置的权重，权重越大同时下载的图片越多
+#[tauri::command]
+async fn set_task_weight(_app: AppHandle, id: i32, weight: i32) -> i32 {
+    let weight = weight.clamp(1, 10);
+    if let Err(e) = update_download_task_weight(id, weight) {
+        error!("update task weight failed: {} e: {}", id, e);
+        return -1;
+    }
+    {
+        let mut tasks = TASKS.write().unwrap();
+        if let Some(task) = tasks.iter_mut().find(|x| x.id == id) {
+            task.weight = weight;
+        }
+    }
+    update_task_weight(id, weight);
+    weight
+}
+
+// 计算移动任务后需要修改的队列位置 (id, queue_position)
+// up / down 与同状态的相邻任务交换，top / bottom 移到整个队列的最前或最后
+fn queue_position_changes(tasks: &Vec<PartialDownloadTask>, id: i32, to: &str) -> Vec<(i32, i32)> {
+    let current = match tasks.iter().find(|x| x.id == id) {
+        Some(task) => task,
+        None => return Vec::new(),
+    };
+    match to {
+        "top" => {
+            let min = tasks.iter().map(|x| x.queue_position).min().unwrap_or(0);
+            vec![(id, min - 1)]
+        }
+        "bottom" => {
+            let max = tasks.iter().map(|x| x.queue_position).max().unwrap_or(0);
+            vec![(id, max + 1)]
+        }
+        "up" | "down" => {
+            let mut same_status: Vec<&PartialDownloadTask> = tasks
+                .iter()
+                .filter(|x| x.status == current.status)
+                .collect();
+            same_status.sort_by_key(|x| (x.queue_position, x.id));
+            let index = same_status.iter().position(|x| x.id == id).unwrap();
+            let neighbor = if to == "up" {
+                index.checked_sub(1).and_then(|i| same_status.get(i))
+            } else {
+                same_status.get(index + 1)
+            };
+            match neighbor {
+                Some(neighbor) => vec![
+                    (id, neighbor.queue_position),
+                    (neighbor.id, current.queue_position),
+                ],
+                None => Vec::new(),
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+// 调整任务在队列中的顺序，waiting 的任务按这个顺序开始下载
+#[tauri::command]
+async fn move_task(_app: AppHandle, id: i32, to: String) -> Vec<PartialDownloadTask> {
+    info!("move_task id: {} to: {}", id, &to);
+    let changes = {
+        let tasks = TASKS.read().unwrap();
+        queue_position_changes(&tasks, id, &to)
+    };
+    if !changes.is_empty() {
+        match update_queue_positions(&changes) {
+            Ok(_) => {
+                let mut tasks = TASKS.write().unwrap();
+                for (task_id, position) in changes.iter() {
+                    if let Some(task) = tasks.iter_mut().find(|x| x.id == *task_id) {
+                        task.queue_position = *position;
+                    }
+                }
+            }
+            Err(e) => {
+                error!("update queue positions failed: {} e: {}", id, e);
+            }
+        }
+        sort_tasks();
+    }
+
+    TASKS.read().unwrap().clone()
+}
+
+// 任务每个章节的下载进度
+#[tauri::command]
+async fn get_task_chapters(_app: AppHandle, id: i32) -> Vec<ChapterProgress> {
+    match get_task_chapter_progress(id) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("get_task_chapter_progress failed: {} e: {}", id, e);
+            Vec::new()
+        }
+    }
+}
+
+// 任务历史，task_id 为空时查询所有任务
+#[tauri::command]
+async fn history_events(_app: AppHandle, task_id: Option<i32>, limit: i64) -> Vec<TaskHistory> {
+    match get_task_history(task_id.unwrap_or(0), limit) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("get_task_history failed: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// 每天（day）或每周（week）的下载量
+#[tauri::command]
+async fn history_totals(_app: AppHandle, period: String, limit: i64) -> Vec<HistoryTotal> {
+    match get_history_totals(&period, limit) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("get_history_totals failed: {} e: {}", &period, e);
+            Vec::new()
+        }
+    }
+}
+
+#[tauri::command]
+async fn history_sites(_app: AppHandle) -> Vec<SiteTotal> {
+    match get_site_totals() {
+        Ok(res) => res,
+        Err(e) => {
+            error!("get_site_totals failed: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// 最慢（slowest）或失败最多（failing）的图片域名
+#[tauri::command]
+async fn history_hosts(_app: AppHandle, order: String, limit: i64) -> Vec<HostTotal> {
+    match get_host_totals(&order, limit) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("get_host_totals failed: {} e: {}", &order, e);
+            Vec::new()
+        }
+    }
+}
+
+#[tauri::command]
+async fn retry_failed(app: AppHandle, id: i32, alternates: Option<Vec<AlternateImg>>) {
+    info!("retry_failed id: {} alternates: {:?}", id, &alternates);
+    let task = match get_download_task(id) {
+        Ok(task) => task,
+        Err(e) => {
+            error!("retry_failed get_download_task failed: {} e: {}", id, e);
+            app.emit("err_msg_main", "retry failed task not found!")
+                .unwrap();
+            return;
+        }
+    };
+    if task.status != "failed" {
+        info!("retry_failed task is not failed: {} {}", id, &task.status);
+        return;
+    }
+
+    for alt in alternates.unwrap_or_default().iter() {
+        if let Err(e) =
+            update_page_alt_href(id, alt.group_index as i32, alt.index as i32, &alt.href)
+        {
+            error!("retry_failed save alternates failed: {} e: {}", id, e);
+        }
+    }
+
+    if let Some(selection) = failed_selection(id) {
+        RETRY_SELECTION.write().unwrap().insert(id, selection);
+    }
+
+    start_or_pause(app, id, String::from("downloading")).await;
+}
+
+#[tauri::command]
+async fn retry_all_failed(_app: AppHandle) -> StartAllRes {
+    info!("retry_all_failed");
+    let failed_ids: Vec<i32> = {
+        let tasks = TASKS.read().unwrap();
+        tasks
+            .iter()
+            .filter(|x| x.status == "failed")
+            .map(|x| x.id)
+            .collect()
+    };
+    for id in failed_ids.iter() {
+        if let Some(selection) = failed_selection(*id) {
+            RETRY_SELECTION.write().unwrap().insert(*id, selection);
+        }
+    }
+
+    let changed = enqueue_tasks(&failed_ids);
+
+    let tasks_res = TASKS.read().unwrap().clone();
+    StartAllRes {
+        tasks: tasks_res,
+        changed,
+    }
+}
+
 // WindowConfig https://docs.rs/tauri-utils/latest/tauri_utils/config/struct.WindowConfig.html
 #[tauri::command]
 async fn add(app: AppHandle) {
@@ -1255,8 +1780,8 @@ async fn setting(app: AppHandle) {
         center: true,
         x: None,
         y: None,
-        width: 600_f64,
-        height: 300_f64,
+        width: 720_f64,
+        height: 660_f64,
         min_width: None,
         min_height: None,
         max_width: None,
@@ -1307,8 +1832,7 @@ async fn download_dir(_app: AppHandle, current_dir: String) -> String {
     let init_dir = if !current_dir.is_empty() {
         PathBuf::from(current_dir)
     } else {
-        let home_dir = home::home_dir().unwrap();
-        home_dir.join(format!(".comic_dl_tauri/download/"))
+        app_paths().default_download_dir()
     };
     let dir = rfd::FileDialog::new().set_directory(init_dir).pick_folder();
     if let Some(res_dir) = dir {
@@ -1328,20 +1852,45 @@ async fn setting_save(
     concurrent_img: String,
     img_timeout: String,
     img_retry_count: String,
+    auto_resume: String,
+    auto_concurrent_img: String,
+    min_free_space: String,
+    name_template: String,
+    filename_rules: String,
+    backup_interval: String,
+    backup_keep: String,
+    cache_max_size: String,
+    cache_ttl: String,
+    schedule: Vec<ScheduleWindow>,
 ) {
     info!(
-        "download_dir: {}, concurrent_task: {}, concurrent_img: {}, img_timeout: {}, img_retry_count: {}",
-        download_dir, concurrent_task, concurrent_img, img_timeout, img_retry_count
+        "download_dir: {}, concurrent_task: {}, concurrent_img: {}, img_timeout: {}, img_retry_count: {}, auto_resume: {}",
+        download_dir, concurrent_task, concurrent_img, img_timeout, img_retry_count, auto_resume
     );
+    let concurrent_img_count = concurrent_img.parse::<usize>().unwrap_or(10);
     let temp = Setting {
         download_dir: download_dir,
         concurrent_task: concurrent_task,
         concurrent_img: concurrent_img,
         img_timeout: img_timeout,
         img_retry_count: img_retry_count,
+        auto_resume: auto_resume,
+        auto_concurrent_img: auto_concurrent_img,
+        min_free_space: min_free_space,
+        name_template: name_template,
+        filename_rules: filename_rules,
+        backup_interval: backup_interval,
+        backup_keep: backup_keep,
+        cache_max_size: cache_max_size,
+        cache_ttl: cache_ttl,
+        schedule: schedule,
     };
-    let home_dir = home::home_dir().unwrap();
-    let setting_path = home_dir.join(format!(".comic_dl_tauri/setting.json"));
+    if let Err(e) = validate_template(&temp.name_template) {
+        app.emit("err_msg_setting", format!("name template: {}", e))
+            .unwrap();
+        return;
+    }
+    let setting_path = app_paths().setting_file();
     let res = save_to_json(&temp, (&setting_path).to_str().unwrap());
 
     match res {
@@ -1352,6 +1901,9 @@ async fn setting_save(
                 let mut setting_lock = SETTING.write().unwrap();
                 *setting_lock = temp;
             }
+            // 同时下载的任务数、图片数和下载时间段可能改变
+            set_img_capacity(concurrent_img_count);
+            send_manager(ManagerMsg::Schedule);
         }
         Err(e) => {
             app.emit("err_msg_setting", format!("setting save failed: {}", e))
@@ -1360,20 +1912,427 @@ async fn setting_save(
     }
 }
 
+// 移动图片前锁定图片库并读取所有任务，有正在下载或等待的任务时返回 None
+// 返回 Some 时移动完成后需要调用 unlock_library
+fn library_tasks(app: &AppHandle) -> Option<Vec<DownloadTask>> {
+    if let Err(e) = lock_library() {
+        app.emit("err_msg_setting", e).unwrap();
+        return None;
+    }
+    let ids: Vec<i32> = TASKS.read().unwrap().iter().map(|x| x.id).collect();
+    let mut tasks: Vec<DownloadTask> = Vec::new();
+    for id in ids {
+        match get_download_task(id) {
+            Ok(task) => tasks.push(task),
+            Err(e) => error!("library_tasks get_download_task failed: {} e: {}", id, e),
+        }
+    }
+    Some(tasks)
+}
+
+// 把当前的设置保存到 setting.json
+fn persist_setting(app: &AppHandle) {
+    let temp = SETTING.read().unwrap().clone();
+    let setting_path = app_paths().setting_file();
+    if let Err(e) = save_to_json(&temp, (&setting_path).to_str().unwrap()) {
+        app.emit("err_msg_setting", format!("setting save failed: {}", e))
+            .unwrap();
+    }
+}
+
+// 按新的模板移动已下载的图片并更新数据库中的路径，完成后保存模板到设置
+#[tauri::command]
+async fn rename_library(app: AppHandle, template: String) -> RenameResult {
+    info!("rename_library template: {}", &template);
+    if let Err(e) = validate_template(&template) {
+        app.emit("err_msg_setting", format!("name template: {}", e))
+            .unwrap();
+        return RenameResult::default();
+    }
+    let tasks = match library_tasks(&app) {
+        Some(tasks) => tasks,
+        None => return RenameResult::default(),
+    };
+    let download_dir = PathBuf::from(SETTING.read().unwrap().download_dir.clone());
+    let template_clone = template.clone();
+    let res = tauri::async_runtime::spawn_blocking(move || {
+        rename_all(&tasks, &template_clone, &download_dir)
+    })
+    .await;
+    let result = match res {
+        Ok(result) => result,
+        Err(e) => {
+            error!("rename_library failed: {}", e);
+            app.emit("err_msg_setting", format!("rename library failed: {}", e))
+                .unwrap();
+            unlock_library();
+            return RenameResult::default();
+        }
+    };
+    info!("rename_library result: {:?}", &result);
+
+    // 之后的下载使用新的模板
+    SETTING.write().unwrap().name_template = template;
+    persist_setting(&app);
+    unlock_library();
+    result
+}
+
+// 把已下载的图片移动到新的下载目录并更新数据库中的路径，全部移动成功后才保存下载目录到设置
+// 通过 move_library_progress 事件发送进度
+#[tauri::command]
+async fn move_library(app: AppHandle, new_dir: String) -> RenameResult {
+    info!("move_library new_dir: {}", &new_dir);
+    let old_dir = PathBuf::from(SETTING.read().unwrap().download_dir.clone());
+    let old_dir_display = old_dir.display().to_string();
+    let new_path = PathBuf::from(&new_dir);
+    if new_dir.trim().is_empty() || !new_path.is_absolute() {
+        app.emit("err_msg_setting", "invalid download dir").unwrap();
+        return RenameResult::default();
+    }
+    if new_path == old_dir {
+        return RenameResult::default();
+    }
+    if new_path.starts_with(&old_dir) || old_dir.starts_with(&new_path) {
+        app.emit(
+            "err_msg_setting",
+            "the new download dir cannot be inside the old one or contain it",
+        )
+        .unwrap();
+        return RenameResult::default();
+    }
+    let app_clone = app.clone();
+    let new_path_clone = new_path.clone();
+    let res = tauri::async_runtime::spawn_blocking(move || {
+        // 开始移动时才检查并锁定，之后开始的任务等到移动完成后使用新的下载目录
+        let tasks = library_tasks(&app_clone)?;
+        let total = count_library_files(&tasks, &old_dir);
+        let mut last_emit = Instant::now();
+        let mut emit_progress = |done: usize| {
+            if done == total || last_emit.elapsed() >= Duration::from_millis(200) {
+                last_emit = Instant::now();
+                let _ = app_clone.emit(
+                    "move_library_progress",
+                    HashMap::from([("done", done), ("total", total)]),
+                );
+            }
+        };
+        let result = relocate_all(&tasks, &old_dir, &new_path_clone, &mut emit_progress);
+        // 有图片没有移动时把已移动的图片移回原目录，继续使用原下载目录，避免图片分散在两个目录
+        let restored = (result.failed > 0 || result.conflict > 0)
+            .then(|| relocate_all(&tasks, &new_path_clone, &old_dir, &mut |_| {}));
+        Some((result, restored))
+    })
+    .await;
+    let (result, restored) = match res {
+        Ok(Some(res)) => res,
+        Ok(None) => return RenameResult::default(),
+        Err(e) => {
+            error!("move_library failed: {}", e);
+            app.emit("err_msg_setting", format!("move library failed: {}", e))
+                .unwrap();
+            unlock_library();
+            return RenameResult::default();
+        }
+    };
+    info!("move_library result: {:?}", &result);
+
+    if let Some(restored) = restored {
+        info!("move_library restored: {:?}", &restored);
+        let msg = if restored.failed > 0 || restored.conflict > 0 {
+            format!(
+                "move library failed: {} files failed, {} conflicts, {} files could not be moved back to {}",
+                result.failed,
+                result.conflict,
+                restored.failed + restored.conflict,
+                old_dir_display
+            )
+        } else {
+            format!(
+                "move library failed: {} files failed, {} conflicts, moved files restored to {}",
+                result.failed, result.conflict, old_dir_display
+            )
+        };
+        app.emit("err_msg_setting", msg).unwrap();
+        unlock_library();
+        return result;
+    }
+
+    SETTING.write().unwrap().download_dir = new_dir;
+    persist_setting(&app);
+    unlock_library();
+    result
+}
+
 #[tauri::command]
 async fn get_setting(_app: AppHandle) -> Setting {
-    let home_dir = home::home_dir().unwrap();
-    let setting_path = home_dir.join(format!(".comic_dl_tauri/setting.json"));
+    let setting_path = app_paths().setting_file();
     let res = read_from_json::<Setting>(&setting_path.to_str().unwrap()).unwrap_or(Setting {
-        download_dir: String::from((&setting_path).to_str().unwrap_or("")),
+        download_dir: app_paths()
+            .default_download_dir()
+            .to_string_lossy()
+            .to_string(),
         concurrent_task: String::from("1"),
         concurrent_img: String::from("10"),
         img_timeout: String::from("5"),
         img_retry_count: String::from("3"),
+        auto_resume: String::from("false"),
+        auto_concurrent_img: String::from("false"),
+        min_free_space: String::from("1024"),
+        name_template: String::from(""),
+        filename_rules: String::from("windows"),
+        backup_interval: String::from("0"),
+        backup_keep: String::from("7"),
+        cache_max_size: String::from("500"),
+        cache_ttl: String::from("30"),
+        schedule: Vec::new(),
     });
     res
 }
 
+// 打开任务的目录，目录还不存在时打开最近的上级目录
+#[tauri::command]
+async fn open_task_dir(app: AppHandle, id: i32) {
+    let task = match get_download_task(id) {
+        Ok(task) => task,
+        Err(e) => {
+            error!("open_task_dir get_download_task failed: {} e: {}", id, e);
+            return;
+        }
+    };
+    let download_dir = PathBuf::from(SETTING.read().unwrap().download_dir.clone());
+    let mut dir = task_dir(&task, &download_dir);
+    while !dir.exists() {
+        dir = match dir.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => break,
+        };
+    }
+    info!("open_task_dir id: {} dir: {}", id, dir.display());
+    if let Err(e) = open::that(&dir) {
+        error!("open_task_dir failed: {}", e);
+        app.emit("err_msg_main", format!("open_dir failed: {}", e))
+            .unwrap();
+    }
+}
+
+// 把本地已有的漫画目录导入为已完成的任务，只导入一部漫画时可以填写来源网址用于之后检查更新
+#[tauri::command]
+async fn import_folder(app: AppHandle, path: String, url: Option<String>) -> ImportResult {
+    info!("import_folder path: {} url: {:?}", &path, &url);
+    let root = PathBuf::from(&path);
+    if path.trim().is_empty() || !root.is_dir() {
+        app.emit("err_msg_add", "import dir not found!").unwrap();
+        return ImportResult::default();
+    }
+    let url = url.map(|x| x.trim().to_string()).filter(|x| !x.is_empty());
+    if let Some(url) = &url {
+        if Url::parse(url).is_err() {
+            app.emit("err_msg_add", format!("url is invalid!")).unwrap();
+            return ImportResult::default();
+        }
+    }
+    let app_clone = app.clone();
+    let res = tauri::async_runtime::spawn_blocking(move || {
+        let comics = scan_folder(&root);
+        let mut result = ImportResult::default();
+        if url.is_some() && comics.len() != 1 {
+            let _ = app_clone.emit(
+                "err_msg_add",
+                format!(
+                    "source url needs exactly one comic, found {} in the dir",
+                    comics.len()
+                ),
+            );
+            return (result, Vec::new());
+        }
+        let mut tasks = Vec::new();
+        for comic in comics.iter() {
+            match import_comic(comic, url.as_deref()) {
+                Ok(Some(task)) => {
+                    result.imported += 1;
+                    result.pages += task.count as usize;
+                    tasks.push(task);
+                }
+                Ok(None) => result.skipped += 1,
+                Err(e) => {
+                    error!("import_comic failed: {} e: {}", comic.dir.display(), e);
+                    result.failed += 1;
+                }
+            }
+        }
+        (result, tasks)
+    })
+    .await;
+    let (result, tasks) = match res {
+        Ok(res) => res,
+        Err(e) => {
+            error!("import_folder failed: {}", e);
+            app.emit("err_msg_add", format!("import folder failed: {}", e))
+                .unwrap();
+            return ImportResult::default();
+        }
+    };
+    for task in tasks.into_iter() {
+        let temp_task = PartialDownloadTask {
+            id: task.id,
+            dl_type: task.dl_type,
+            status: task.status,
+            local_path: task.local_path,
+            url: task.url,
+            author: task.author,
+            comic_name: task.comic_name,
+            progress: task.progress,
+            count: task.count,
+            now_count: task.now_count,
+            error_vec: task.error_vec,
+            done: task.done,
+            category: task.category,
+            author_id: task.author_id,
+            weight: task.weight,
+            queue_position: task.queue_position,
+            pause_reason: task.pause_reason,
+        };
+        TASKS.write().unwrap().push(temp_task.clone());
+        app.emit("new_task", &temp_task).unwrap();
+    }
+    sort_tasks();
+    info!("import_folder result: {:?}", &result);
+    result
+}
+
+// 自动备份所在的目录
+#[tauri::command]
+async fn get_backup_dir(_app: AppHandle) -> String {
+    app_paths().backup_dir().display().to_string()
+}
+
+// 选择备份文件，save 为 true 时选择保存的位置
+#[tauri::command]
+async fn backup_file_dialog(_app: AppHandle, save: bool) -> String {
+    let init_dir = app_paths().backup_dir();
+    if let Err(e) = fs::create_dir_all(&init_dir) {
+        error!("create backup dir failed: {}", e);
+    }
+    let dialog = rfd::FileDialog::new()
+        .set_directory(init_dir)
+        .add_filter("zip", &["zip"]);
+    let file = if save {
+        dialog.set_file_name(default_backup_name()).save_file()
+    } else {
+        dialog.pick_file()
+    };
+    match file {
+        Some(file) => file.to_str().unwrap_or("").to_string(),
+        None => String::from(""),
+    }
+}
+
+// 备份数据库、设置和缓存到 zip 文件
+#[tauri::command]
+async fn backup(app: AppHandle, path: String) -> BackupInfo {
+    info!("backup path: {}", &path);
+    if path.trim().is_empty() {
+        return BackupInfo::default();
+    }
+    let res = tauri::async_runtime::spawn_blocking(move || backup_to(Path::new(&path))).await;
+    match res.unwrap_or_else(|e| Err(e.to_string())) {
+        Ok(info) => info,
+        Err(e) => {
+            error!("backup failed: {}", e);
+            app.emit("err_msg_setting", format!("backup failed: {}", e))
+                .unwrap();
+            BackupInfo::default()
+        }
+    }
+}
+
+// 从备份恢复，恢复前会把当前数据备份到 backup 目录
+#[tauri::command]
+async fn restore(app: AppHandle, path: String) -> bool {
+    info!("restore path: {}", &path);
+    if path.trim().is_empty() {
+        return false;
+    }
+    let busy = TASKS
+        .read()
+        .unwrap()
+        .iter()
+        .any(|x| x.status == "downloading" || x.status == "waiting");
+    if busy {
+        app.emit(
+            "err_msg_setting",
+            "pause all downloading and waiting tasks before restoring",
+        )
+        .unwrap();
+        return false;
+    }
+    let res = tauri::async_runtime::spawn_blocking(move || restore_from(Path::new(&path))).await;
+    match res.unwrap_or_else(|e| Err(e.to_string())) {
+        Ok(_) => {
+            reload_data(&app);
+            true
+        }
+        Err(e) => {
+            error!("restore failed: {}", e);
+            app.emit("err_msg_setting", format!("restore failed: {}", e))
+                .unwrap();
+            false
+        }
+    }
+}
+
+// 恢复后升级数据库结构，重新读取任务和设置，通知主窗口刷新任务列表
+fn reload_data(app: &AppHandle) {
+    if let Err(e) = create_table() {
+        error!("create_table failed: {}", e.to_string());
+        app.emit("err_msg_main", e.to_string()).unwrap();
+        return;
+    }
+    if let Err(e) = reset_interrupted_tasks() {
+        error!("reset_interrupted_tasks failed: {}", e.to_string());
+    }
+    match get_all_download_tasks() {
+        Ok(data) => {
+            *TASKS.write().unwrap() = data;
+            sort_tasks();
+        }
+        Err(e) => {
+            error!("get_all_download_tasks failed: {}", e.to_string());
+        }
+    }
+    let setting_path = app_paths().setting_file();
+    match read_from_json::<Setting>(&setting_path.to_str().unwrap()) {
+        Ok(setting) => {
+            set_img_capacity(setting.concurrent_img.parse::<usize>().unwrap_or(10));
+            *SETTING.write().unwrap() = setting;
+        }
+        Err(e) => {
+            error!("read setting failed: {}", e);
+        }
+    }
+    send_manager(ManagerMsg::Schedule);
+    app.emit("tasks_reload", ()).unwrap();
+}
+
+// 每类缓存的文件数和大小
+#[tauri::command]
+async fn get_cache_stats(_app: AppHandle) -> CacheStats {
+    tauri::async_runtime::spawn_blocking(cache_stats)
+        .await
+        .unwrap_or_default()
+}
+
+// 按设置的过期时间和大小上限立即清理缓存
+#[tauri::command]
+async fn clean_cache(_app: AppHandle) -> PruneResult {
+    let res = tauri::async_runtime::spawn_blocking(prune_cache)
+        .await
+        .unwrap_or_default();
+    info!("clean_cache: {:?}", &res);
+    res
+}
+
 #[tauri::command]
 async fn open_dir(app: AppHandle, dir: String) {
     info!("open_dir dir: {}", dir);
@@ -1390,8 +2349,7 @@ async fn open_dir(app: AppHandle, dir: String) {
 
 #[tauri::command]
 async fn open_cache_folder(app: AppHandle) {
-    let home_dir = home::home_dir().unwrap();
-    let cache_dir = home_dir.join(format!(".comic_dl_tauri"));
+    let cache_dir = app_paths().cache_dir.clone();
 
     let res = open::that(cache_dir);
     match res {
@@ -1417,7 +2375,7 @@ async fn open_about_winfow(app: AppHandle) {
         x: None,
         y: None,
         width: 300_f64,
-        height: 300_f64,
+        height: 340_f64,
         min_width: None,
         min_height: None,
         max_width: None,
@@ -1463,8 +2421,54 @@ async fn open_about_winfow(app: AppHandle) {
 }
 
 #[tauri::command]
-async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
-    info!("add_new_task url: {}, type: {}", &url, &dl_type);
+async fn preview_new_task(
+    app: AppHandle,
+    url: String,
+    cache: Option<CacheMode>,
+) -> Vec<ChapterPreviewGroup> {
+    info!("preview_new_task url: {} cache: {:?}", &url, &cache);
+    if !url.starts_with("https://www.antbyw.com/")
+        || get_url_query(url.clone(), String::from("kuid")).is_empty()
+    {
+        app.emit("err_msg_add", "only antbyw comic url can be previewed!")
+            .unwrap();
+        return Vec::new();
+    }
+    let res: HandleHtmlRes = handle_html(
+        url.clone(),
+        String::from("juan_hua_fanwai"),
+        cache.unwrap_or_default(),
+        &app,
+    )
+    .await;
+    if res.code != StatusCode::Success {
+        app.emit("err_msg_add", "handle juan_hua_fanwai html failed!")
+            .unwrap();
+        return Vec::new();
+    }
+    preview_chapters(&res)
+}
+
+#[tauri::command]
+async fn add_new_task(
+    app: AppHandle,
+    url: String,
+    dl_type: String,
+    selection: Option<ChapterSelection>,
+    cache: Option<CacheMode>,
+) {
+    info!(
+        "add_new_task url: {}, type: {}, selection: {:?}, cache: {:?}",
+        &url, &dl_type, &selection, &cache
+    );
+    let mode = cache.unwrap_or_default();
+    // 刷新已有的漫画时补充下载新增的章节
+    let selection = match selection {
+        None if mode != CacheMode::Prefer && !["current", "author"].contains(&dl_type.as_str()) => {
+            Some(ChapterSelection::default())
+        }
+        _ => selection,
+    };
 
     let dl_type_temp = dl_type.as_str();
     if url.is_empty()
@@ -1481,7 +2485,8 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                     let no_find;
                     match db_task_res {
                         Ok(data) => {
-                            if data.is_empty() {
+                            // 指定了章节选择时允许补充下载已有任务缺少的章节
+                            if data.is_empty() || selection.is_some() {
                                 no_find = true;
                             } else {
                                 no_find = false;
@@ -1500,21 +2505,26 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
 
                     if no_find {
                         let res: HandleHtmlRes =
-                            handle_html(url.clone(), dl_type.clone(), &app).await;
+                            handle_html(url.clone(), dl_type.clone(), mode, &app).await;
                         info!("{:?}", res.code());
                         match dl_type_temp {
                             "current" => {
                                 if res.code == StatusCode::Success && res.done {
                                     if let DataWrapper::VecData(current_data) = res.data.clone() {
-                                        let current_data_json =
-                                            serde_json::to_string_pretty(&current_data).unwrap();
                                         let current_name: String =
                                             res.comic_name + "_" + &res.current_name;
+                                        let current_groups = vec![CurrentElement {
+                                            name: res.current_name.clone(),
+                                            href: url.clone(),
+                                            count: current_data.len(),
+                                            imgs: current_data,
+                                            done: false,
+                                        }];
                                         let db_res = create_download_task(
                                             &dl_type_temp,
                                             "stopped",
                                             &res.local,
-                                            &current_data_json,
+                                            &current_groups,
                                             &url,
                                             &clean_string(&res.author),
                                             &clean_string(&current_name),
@@ -1523,6 +2533,8 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                                             0 as i32,
                                             "",
                                             false,
+                                            "",
+                                            0,
                                         );
                                         match db_res {
                                             Ok(task) => {
@@ -1539,6 +2551,11 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                                                     now_count: task.now_count,
                                                     error_vec: task.error_vec,
                                                     done: task.done,
+                                                    category: task.category,
+                                                    author_id: task.author_id,
+                                                    weight: task.weight,
+                                                    queue_position: task.queue_position,
+                                                    pause_reason: task.pause_reason,
                                                 };
 
                                                 let tasks_to_log = {
@@ -1574,19 +2591,17 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                             }
                             "juan" => {
                                 if res.code == StatusCode::Success && res.done {
-                                    if let DataWrapper::HashMapData(juan_hua_fanwai_data) =
-                                        res.data.clone()
-                                    {
-                                        for (key, value) in juan_hua_fanwai_data.iter() {
-                                            if key == "单行本" {
-                                                add_new_task_juan_hua_fanwai(
-                                                    key.to_string(),
-                                                    value,
-                                                    &res,
-                                                    &app,
-                                                    url.clone(),
-                                                );
-                                            }
+                                    for category in res.data.categories() {
+                                        if category.name == "单行本" {
+                                            add_new_task_juan_hua_fanwai(
+                                                category.name.clone(),
+                                                &category.chapters,
+                                                &res,
+                                                &app,
+                                                url.clone(),
+                                                selection.as_ref(),
+                                                0,
+                                            );
                                         }
                                     }
                                 } else {
@@ -1596,19 +2611,17 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                             }
                             "hua" => {
                                 if res.code == StatusCode::Success && res.done {
-                                    if let DataWrapper::HashMapData(juan_hua_fanwai_data) =
-                                        res.data.clone()
-                                    {
-                                        for (key, value) in juan_hua_fanwai_data.iter() {
-                                            if key == "单话" {
-                                                add_new_task_juan_hua_fanwai(
-                                                    key.to_string(),
-                                                    value,
-                                                    &res,
-                                                    &app,
-                                                    url.clone(),
-                                                );
-                                            }
+                                    for category in res.data.categories() {
+                                        if category.name == "单话" {
+                                            add_new_task_juan_hua_fanwai(
+                                                category.name.clone(),
+                                                &category.chapters,
+                                                &res,
+                                                &app,
+                                                url.clone(),
+                                                selection.as_ref(),
+                                                0,
+                                            );
                                         }
                                     }
                                 } else {
@@ -1618,19 +2631,17 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                             }
                             "fanwai" => {
                                 if res.code == StatusCode::Success && res.done {
-                                    if let DataWrapper::HashMapData(juan_hua_fanwai_data) =
-                                        res.data.clone()
-                                    {
-                                        for (key, value) in juan_hua_fanwai_data.iter() {
-                                            if key == "番外篇" {
-                                                add_new_task_juan_hua_fanwai(
-                                                    key.to_string(),
-                                                    value,
-                                                    &res,
-                                                    &app,
-                                                    url.clone(),
-                                                );
-                                            }
+                                    for category in res.data.categories() {
+                                        if category.name == "番外篇" {
+                                            add_new_task_juan_hua_fanwai(
+                                                category.name.clone(),
+                                                &category.chapters,
+                                                &res,
+                                                &app,
+                                                url.clone(),
+                                                selection.as_ref(),
+                                                0,
+                                            );
                                         }
                                     }
                                 } else {
@@ -1640,18 +2651,16 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                             }
                             "juan_hua_fanwai" => {
                                 if res.code == StatusCode::Success && res.done {
-                                    if let DataWrapper::HashMapData(juan_hua_fanwai_data) =
-                                        res.data.clone()
-                                    {
-                                        for (key, value) in juan_hua_fanwai_data.iter() {
-                                            add_new_task_juan_hua_fanwai(
-                                                key.to_string(),
-                                                value,
-                                                &res,
-                                                &app,
-                                                url.clone(),
-                                            );
-                                        }
+                                    for category in res.data.categories() {
+                                        add_new_task_juan_hua_fanwai(
+                                            category.name.clone(),
+                                            &category.chapters,
+                                            &res,
+                                            &app,
+                                            url.clone(),
+                                            selection.as_ref(),
+                                            0,
+                                        );
                                     }
                                 } else {
                                     app.emit("err_msg_add", "handle juan_hua_fanwai html failed!")
@@ -1663,22 +2672,18 @@ async fn add_new_task(app: AppHandle, url: String, dl_type: String) {
                                     if let DataWrapper::VecAuthorData(author_data) =
                                         res.data.clone()
                                     {
-                                        for data in author_data.iter() {
-                                            let comic_json =
-                                                read_from_json::<HandleHtmlRes>(&data.local)
+                                        match find_or_create_author(
+                                            &res.author,
+                                            &url,
+                                            author_data.len() as i32,
+                                        ) {
+                                            Ok(author) => {
+                                                queue_author_comics(&app, author.id, &author_data);
+                                            }
+                                            Err(e) => {
+                                                error!("insert author failed: {}", e.to_string());
+                                                app.emit("err_msg_main", "insert author failed!")
                                                     .unwrap();
-                                            if let DataWrapper::HashMapData(juan_hua_fanwai_data) =
-                                                comic_json.data.clone()
-                                            {
-                                                for (key, value) in juan_hua_fanwai_data.iter() {
-                                                    add_new_task_juan_hua_fanwai(
-                                                        key.to_string(),
-                                                        value,
-                                                        &comic_json,
-                                                        &app,
-                                                        data.url.clone(),
-                                                    );
-                                                }
                                             }
                                         }
                                     }
@@ -1714,18 +2719,36 @@ pub fn add_new_task_juan_hua_fanwai(
     res: &HandleHtmlRes,
     app: &AppHandle,
     url: String,
+    selection: Option<&ChapterSelection>,
+    author_id: i32,
 ) {
     let dl_type_divide = match key.as_str() {
         "单行本" => "juan",
         "单话" => "hua",
         "番外篇" => "fanwai",
-        _ => "",
+        _ => "category",
+    };
+    // 其他类别（例如 短篇 特别篇）保存原始标题
+    let category = if dl_type_divide == "category" {
+        key.as_str()
+    } else {
+        ""
     };
-    let db_task_res = find_tasks_by_dl_type_and_url(dl_type_divide, &url);
+    let db_task_res = find_tasks_by_dl_type_and_url(dl_type_divide, &url).map(|data| {
+        data.into_iter()
+            .filter(|x| x.category == category)
+            .collect::<Vec<_>>()
+    });
+    // 已有任务中包含的章节，补充下载时跳过
+    let mut existing_hrefs: HashSet<String> = HashSet::new();
     let no_find: bool = match db_task_res {
         Ok(data) => {
             let res = if data.is_empty() {
                 true
+            } else if selection.is_some() {
+                let task_ids = data.iter().map(|x| x.id).collect::<Vec<_>>();
+                existing_hrefs.extend(get_chapter_hrefs_by_task_ids(task_ids).unwrap_or_default());
+                true
             } else {
                 info!(
                     "already has this task: dl_type_divide: {} url: {}",
@@ -1746,9 +2769,25 @@ pub fn add_new_task_juan_hua_fanwai(
         }
     };
     if no_find {
+        let selected = match selection {
+            Some(selection) => {
+                let mut temp = select_chapters(value, selection);
+                temp.retain(|x| !existing_hrefs.contains(&x.href));
+                temp
+            }
+            None => value.clone(),
+        };
+        if selected.is_empty() {
+            info!(
+                "no chapter selected: dl_type_divide: {} url: {}",
+                &dl_type_divide, &url
+            );
+            app.emit_to("main", "info_msg_main", "no new chapter selected!")
+                .unwrap();
+            return;
+        }
         let mut all_count: i32 = 0;
-        let new_value = value
-            .clone()
+        let new_value = selected
             .iter()
             .map(|x| {
                 all_count += x.count as i32;
@@ -1762,13 +2801,12 @@ pub fn add_new_task_juan_hua_fanwai(
                 temp
             })
             .collect::<Vec<_>>();
-        let data_json = serde_json::to_string_pretty(&new_value).unwrap();
         info!("dl_type_divide: {}", dl_type_divide);
         let db_res = create_download_task(
             dl_type_divide,
             "stopped",
             &res.local,
-            &data_json,
+            &new_value,
             &url,
             &clean_string(&res.author),
             &clean_string(&res.comic_name),
@@ -1777,6 +2815,8 @@ pub fn add_new_task_juan_hua_fanwai(
             0 as i32,
             "",
             false,
+            category,
+            author_id,
         );
         match db_res {
             Ok(task) => {
@@ -1793,6 +2833,11 @@ pub fn add_new_task_juan_hua_fanwai(
                     now_count: task.now_count,
                     error_vec: task.error_vec,
                     done: task.done,
+                    category: task.category,
+                    author_id: task.author_id,
+                    weight: task.weight,
+                    queue_position: task.queue_position,
+                    pause_reason: task.pause_reason,
                 };
 
                 let tasks_to_log = {
@@ -1817,3 +2862,318 @@ pub fn add_new_task_juan_hua_fanwai(
         }
     }
 }
+
+// 把作者页中的漫画添加为任务
+fn queue_author_comics(app: &AppHandle, author_id: i32, comics: &Vec<AuthorElement>) {
+    for data in comics.iter() {
+        let comic_json = match read_from_json::<HandleHtmlRes>(&data.local) {
+            Ok(comic_json) => comic_json,
+            Err(e) => {
+                error!("read comic json failed: {} e: {}", &data.local, e);
+                continue;
+            }
+        };
+        for category in comic_json.data.categories() {
+            add_new_task_juan_hua_fanwai(
+                category.name.clone(),
+                &category.chapters,
+                &comic_json,
+                app,
+                data.url.clone(),
+                None,
+                author_id,
+            );
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_authors(_app: AppHandle) -> Vec<AuthorSummary> {
+    let authors = match get_all_authors() {
+        Ok(authors) => authors,
+        Err(e) => {
+            error!("get_all_authors failed: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut res: Vec<AuthorSummary> = Vec::new();
+    for author in authors.into_iter() {
+        let tasks = get_download_tasks_by_author_id(author.id).unwrap_or_default();
+        let mut comics: HashSet<&str> = HashSet::new();
+        let mut summary = AuthorSummary {
+            id: author.id,
+            name: author.name.clone(),
+            url: author.url.clone(),
+            updated_at: author.updated_at.clone(),
+            comic_count: author.comic_count,
+            queued_comic_count: 0,
+            task_count: tasks.len() as i32,
+            finished_count: 0,
+            failed_count: 0,
+            downloading_count: 0,
+            count: 0,
+            now_count: 0,
+            progress: String::from("0.00"),
+        };
+        for task in tasks.iter() {
+            comics.insert(task.url.as_str());
+            summary.count += task.count;
+            summary.now_count += task.now_count;
+            match task.status.as_str() {
+                "finished" => summary.finished_count += 1,
+                "failed" => summary.failed_count += 1,
+                "downloading" | "waiting" => summary.downloading_count += 1,
+                _ => {}
+            }
+        }
+        summary.queued_comic_count = comics.len() as i32;
+        if summary.count > 0 {
+            summary.progress = format!(
+                "{:.2}",
+                (summary.now_count as f32) / (summary.count as f32) * 100.00
+            );
+        }
+        res.push(summary);
+    }
+    res
+}
+
+// 用重新抓取的图片更新任务的章节，fresh 的 key 为章节链接
+// 没有下载完成且链接变化的图片保存为备用链接，多出的图片追加到章节末尾，返回 (更新数, 追加数)
+fn refresh_task_pages(
+    app: &AppHandle,
+    id: i32,
+    fresh: &HashMap<String, Vec<Img>>,
+) -> (usize, usize) {
+    let groups = get_task_groups(id).unwrap_or_default();
+    let mut updated = 0;
+    let mut added = 0;
+    let mut changed_task = None;
+    for (group_index, group) in groups.iter().enumerate() {
+        let imgs = match fresh.get(&group.href) {
+            Some(imgs) => imgs,
+            None => continue,
+        };
+        for (i, (old, new)) in group.imgs.iter().zip(imgs.iter()).enumerate() {
+            if old.done || old.href == new.href || old.alt_href.as_ref() == Some(&new.href) {
+                continue;
+            }
+            match update_page_alt_href(id, group_index as i32, i as i32, &new.href) {
+                Ok(_) => updated += 1,
+                Err(e) => error!("refresh_task update_page_alt_href failed: {} e: {}", id, e),
+            }
+        }
+        if imgs.len() > group.imgs.len() {
+            let hrefs: Vec<String> = imgs[group.imgs.len()..]
+                .iter()
+                .map(|x| x.href.clone())
+                .collect();
+            match append_task_pages(id, group_index as i32, &hrefs) {
+                Ok(task) => {
+                    added += hrefs.len();
+                    changed_task = Some(task);
+                }
+                Err(e) => error!("refresh_task append_task_pages failed: {} e: {}", id, e),
+            }
+        }
+    }
+    if let Some(task) = changed_task {
+        {
+            let mut tasks = TASKS.write().unwrap();
+            if let Some(temp) = tasks.iter_mut().find(|x| x.id == id) {
+                temp.count = task.count;
+                temp.progress = task.progress;
+                temp.status = task.status;
+                temp.done = task.done;
+            }
+        }
+        app.emit("tasks_reload", ()).unwrap();
+    }
+    info!(
+        "refresh_task_pages {} updated: {} added: {}",
+        id, updated, added
+    );
+    (updated, added)
+}
+
+// 重新验证已有任务的页面缓存，更新章节中变化和新增的图片，漫画任务补充新增的章节
+#[tauri::command]
+async fn refresh_task(app: AppHandle, id: i32, cache: Option<CacheMode>) {
+    let task = match get_download_task(id) {
+        Ok(task) => task,
+        Err(e) => {
+            error!("refresh_task get_download_task failed: {} e: {}", id, e);
+            app.emit("err_msg_main", "refresh task not found!").unwrap();
+            return;
+        }
+    };
+    // 默认重新验证，页面没有变化时不用重新下载
+    let mode = match cache {
+        None | Some(CacheMode::Prefer) => CacheMode::Revalidate,
+        Some(mode) => mode,
+    };
+    info!(
+        "refresh_task: {} {} {} {:?}",
+        id, &task.dl_type, &task.url, mode
+    );
+    if task.status == "downloading" || task.status == "waiting" {
+        app.emit("err_msg_main", "stop the task before refreshing!")
+            .unwrap();
+        return;
+    }
+    if !task.url.starts_with("https://www.antbyw.com/") {
+        app.emit("err_msg_main", "only antbyw tasks can be refreshed!")
+            .unwrap();
+        return;
+    }
+
+    if task.dl_type == "current" {
+        let res = handle_html(task.url.clone(), task.dl_type.clone(), mode, &app).await;
+        let imgs = match res.data {
+            DataWrapper::VecData(imgs) if res.code == StatusCode::Success => imgs,
+            _ => {
+                app.emit("err_msg_main", "handle current html failed!")
+                    .unwrap();
+                return;
+            }
+        };
+        let (updated, added) =
+            refresh_task_pages(&app, id, &HashMap::from([(task.url.clone(), imgs)]));
+        app.emit(
+            "info_msg_main",
+            format!("{} pages updated, {} pages added", updated, added),
+        )
+        .unwrap();
+        return;
+    }
+
+    let key = match task.dl_type.as_str() {
+        "juan" => "单行本",
+        "hua" => "单话",
+        "fanwai" => "番外篇",
+        _ => task.category.as_str(),
+    };
+    let res = handle_html(
+        task.url.clone(),
+        String::from("juan_hua_fanwai"),
+        mode,
+        &app,
+    )
+    .await;
+    if res.code != StatusCode::Success || !res.done {
+        app.emit("err_msg_main", "handle juan_hua_fanwai html failed!")
+            .unwrap();
+        return;
+    }
+    let categories = res.data.categories();
+    // 已有章节中重新抓取后多出的图片
+    let fresh: HashMap<String, Vec<Img>> = categories
+        .iter()
+        .flat_map(|x| x.chapters.iter())
+        .map(|x| (x.href.clone(), x.imgs.clone()))
+        .collect();
+    let (updated, added) = refresh_task_pages(&app, id, &fresh);
+    if updated > 0 || added > 0 {
+        app.emit(
+            "info_msg_main",
+            format!("{} pages updated, {} pages added", updated, added),
+        )
+        .unwrap();
+    }
+    match categories.iter().find(|x| x.name == key) {
+        // 已有任务中的章节会跳过，只添加新增的章节
+        Some(category) => add_new_task_juan_hua_fanwai(
+            category.name.clone(),
+            &category.chapters,
+            &res,
+            &app,
+            task.url.clone(),
+            Some(&ChapterSelection::default()),
+            task.author_id,
+        ),
+        None => {
+            app.emit("info_msg_main", "no new chapter selected!")
+                .unwrap();
+        }
+    }
+}
+
+// 重新抓取作者页，返回还没有添加为任务的新漫画
+#[tauri::command]
+async fn refresh_author(app: AppHandle, id: i32) -> Vec<AuthorElement> {
+    let author = match get_author(id) {
+        Ok(author) => author,
+        Err(e) => {
+            error!("refresh_author get_author failed: {} e: {}", id, e);
+            app.emit("err_msg_main", "author not found!").unwrap();
+            return Vec::new();
+        }
+    };
+    info!("refresh_author: {:?}", &author);
+
+    // 重新验证作者页缓存，页面没有变化时服务器返回 304 直接使用缓存
+    let res: HandleHtmlRes = handle_html(
+        author.url.clone(),
+        String::from("author"),
+        CacheMode::Revalidate,
+        &app,
+    )
+    .await;
+    if res.code != StatusCode::Success {
+        app.emit("err_msg_main", "handle author html failed!")
+            .unwrap();
+        return Vec::new();
+    }
+    let author_data = if let DataWrapper::VecAuthorData(author_data) = res.data {
+        author_data
+    } else {
+        Vec::new()
+    };
+    if let Err(e) = update_author_refreshed(id, author_data.len() as i32) {
+        error!("update_author_refreshed failed: {} e: {}", id, e);
+    }
+
+    let queued: HashSet<String> = get_download_tasks_by_author_id(id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| x.url)
+        .collect();
+    let new_comics: Vec<AuthorElement> = author_data
+        .into_iter()
+        .filter(|x| !queued.contains(&x.url))
+        .collect();
+    info!(
+        "refresh_author new comics: {} {:?}",
+        new_comics.len(),
+        &new_comics
+    );
+    new_comics
+}
+
+// 添加作者刷新后选中的新漫画
+#[tauri::command]
+async fn add_author_comics(app: AppHandle, id: i32, urls: Vec<String>) {
+    let author = match get_author(id) {
+        Ok(author) => author,
+        Err(e) => {
+            error!("add_author_comics get_author failed: {} e: {}", id, e);
+            app.emit("err_msg_main", "author not found!").unwrap();
+            return;
+        }
+    };
+    let res: HandleHtmlRes = handle_html(
+        author.url.clone(),
+        String::from("author"),
+        CacheMode::Prefer,
+        &app,
+    )
+    .await;
+    if let DataWrapper::VecAuthorData(author_data) = res.data {
+        let selected: Vec<AuthorElement> = author_data
+            .into_iter()
+            .filter(|x| urls.contains(&x.url) && x.done)
+            .collect();
+        queue_author_comics(&app, id, &selected);
+    }
+}
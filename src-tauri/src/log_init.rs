@@ -1,3 +1,4 @@
+use crate::paths::app_paths;
 use log::{debug, error, info, trace, warn, LevelFilter, SetLoggerError};
 use log4rs::{
     append::{
@@ -31,17 +32,15 @@ pub fn init_log() -> Result<(), SetLoggerError> {
     //     .expect("Time went backwards");
     let timestamp = chrono::Local::now().format("%Y-%m-%d").to_string();
 
-    let home_dir = home::home_dir().unwrap();
-    let log_path = home_dir
-        .join(".comic_dl_tauri")
-        .join(format!("log/log-{}.log", timestamp));
-    let archive_log_path = home_dir.join(".comic_dl_tauri").join("log/archive.{}.log");
+    let log_dir = &app_paths().log_dir;
+    let log_path = log_dir.join(format!("log-{}.log", timestamp));
+    let archive_log_path = log_dir.join("archive.{}.log");
     let file_path: &str = log_path.to_str().unwrap();
     let archive_path: &str = archive_log_path.to_str().unwrap();
 
     println!(
         "\n {} \n{} \n {}\n",
-        &home_dir.to_str().unwrap(),
+        &log_dir.to_str().unwrap(),
         &file_path,
         &archive_path
     );
@@ -15,7 +15,9 @@ use std::{fs, process};
 use tokio::sync::Semaphore;
 use tokio::time::timeout;
 
-use crate::utils::{create_file_if_not_exists, format_to_string, handle_img_extension, handle_url};
+use crate::utils::{
+    create_file_if_not_exists, format_to_string, handle_img_extension, handle_url, ChapterKey,
+};
 
 /**
  * Aggregate response
@@ -189,13 +191,11 @@ pub async fn handle_mangadex(url: String) -> Result<()> {
     url_vec.sort_by(|a, b| {
         let a_info = serial_hashmap.get(a).unwrap();
         let b_info = serial_hashmap.get(b).unwrap();
-        let a_volume = a_info.volume.parse::<i32>().unwrap_or_default();
-        let a_chapter = a_info.chapter.parse::<i32>().unwrap_or_default();
-        let b_volume = b_info.volume.parse::<i32>().unwrap_or_default();
-        let b_chapter = b_info.chapter.parse::<i32>().unwrap_or_default();
-        a_volume
-            .cmp(&b_volume)
-            .then_with(|| a_chapter.cmp(&b_chapter))
+        let a_key = ChapterKey::from_pair(&a_info.volume, &a_info.chapter);
+        let b_key = ChapterKey::from_pair(&b_info.volume, &b_info.chapter);
+        a_key
+            .cmp(&b_key)
+            .then_with(|| a_info.chapter.cmp(&b_info.chapter))
     });
 
     println!(
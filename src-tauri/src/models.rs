@@ -1,4 +1,4 @@
-use crate::schema::download_tasks;
+use crate::schema::{authors, chapters, download_tasks, host_history, pages, series, task_history};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -19,6 +19,11 @@ pub struct DownloadTask {
     pub now_count: i32,
     pub error_vec: String,
     pub done: bool,
+    pub category: String,
+    pub author_id: i32,
+    pub weight: i32,
+    pub queue_position: i32,
+    pub pause_reason: String,
 }
 
 #[derive(Insertable, Queryable, Selectable, PartialEq, Debug, Deserialize, Serialize, Clone)]
@@ -36,6 +41,9 @@ pub struct NewDownloadTask<'a> {
     pub(crate) now_count: i32,
     pub(crate) error_vec: &'a str,
     pub(crate) done: bool,
+    pub(crate) category: &'a str,
+    pub(crate) author_id: i32,
+    pub(crate) queue_position: i32,
 }
 
 #[derive(Queryable, Selectable, PartialEq, Debug, Deserialize, Serialize, Clone)]
@@ -54,4 +62,217 @@ pub struct PartialDownloadTask {
     pub now_count: i32,
     pub error_vec: String,
     pub done: bool,
+    pub category: String,
+    pub author_id: i32,
+    pub weight: i32,
+    pub queue_position: i32,
+    pub pause_reason: String,
+}
+
+#[derive(Queryable, Selectable, PartialEq, Debug, Deserialize, Serialize, Clone)]
+#[diesel(table_name = authors)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Author {
+    pub id: i32,
+    pub name: String,
+    pub url: String,
+    pub comic_count: i32,
+    pub updated_at: String,
+}
+
+#[derive(Insertable, PartialEq, Debug, Clone)]
+#[diesel(table_name = authors)]
+pub struct NewAuthor<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) url: &'a str,
+    pub(crate) comic_count: i32,
+    pub(crate) updated_at: &'a str,
+}
+
+#[derive(Queryable, Selectable, PartialEq, Debug, Deserialize, Serialize, Clone)]
+#[diesel(table_name = series)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Series {
+    pub id: i32,
+    pub url: String,
+    pub comic_name: String,
+    pub author: String,
+    pub author_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Debug, Clone)]
+#[diesel(table_name = series)]
+pub struct NewSeries<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) comic_name: &'a str,
+    pub(crate) author: &'a str,
+    pub(crate) author_id: i32,
+}
+
+#[derive(Queryable, Selectable, PartialEq, Debug, Deserialize, Serialize, Clone)]
+#[diesel(table_name = chapters)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Chapter {
+    pub id: i32,
+    pub series_id: i32,
+    pub task_id: i32,
+    pub group_index: i32,
+    pub name: String,
+    pub href: String,
+    pub count: i32,
+}
+
+#[derive(Insertable, PartialEq, Debug, Clone)]
+#[diesel(table_name = chapters)]
+pub struct NewChapter<'a> {
+    pub(crate) series_id: i32,
+    pub(crate) task_id: i32,
+    pub(crate) group_index: i32,
+    pub(crate) name: &'a str,
+    pub(crate) href: &'a str,
+    pub(crate) count: i32,
+}
+
+#[derive(Queryable, Selectable, PartialEq, Debug, Deserialize, Serialize, Clone)]
+#[diesel(table_name = pages)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Page {
+    pub id: i32,
+    pub chapter_id: i32,
+    pub task_id: i32,
+    pub group_index: i32,
+    pub page_index: i32,
+    pub href: String,
+    pub alt_href: Option<String>,
+    pub done: bool,
+    pub error_msg: String,
+    pub file_path: String,
+}
+
+#[derive(Insertable, PartialEq, Debug, Clone)]
+#[diesel(table_name = pages)]
+pub struct NewPage<'a> {
+    pub(crate) chapter_id: i32,
+    pub(crate) task_id: i32,
+    pub(crate) group_index: i32,
+    pub(crate) page_index: i32,
+    pub(crate) href: &'a str,
+    pub(crate) alt_href: Option<&'a str>,
+    pub(crate) done: bool,
+    pub(crate) error_msg: &'a str,
+    pub(crate) file_path: &'a str,
+}
+
+// 章节下载进度，由 pages 表统计
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterProgress {
+    pub group_index: i32,
+    pub name: String,
+    pub href: String,
+    pub count: i32,
+    pub done_count: i32,
+    pub failed_count: i32,
+}
+
+// 任务历史事件：added started paused finished failed deleted
+#[derive(Queryable, Selectable, PartialEq, Debug, Serialize, Clone)]
+#[diesel(table_name = task_history)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TaskHistory {
+    pub id: i32,
+    pub task_id: i32,
+    pub event: String,
+    pub comic_name: String,
+    pub author: String,
+    pub dl_type: String,
+    pub site: String,
+    pub bytes: i64,
+    pub duration_ms: i64,
+    pub img_count: i32,
+    pub error_count: i32,
+    pub created_at: String,
+}
+
+#[derive(Insertable, PartialEq, Debug, Clone)]
+#[diesel(table_name = task_history)]
+pub struct NewTaskHistory<'a> {
+    pub(crate) task_id: i32,
+    pub(crate) event: &'a str,
+    pub(crate) comic_name: &'a str,
+    pub(crate) author: &'a str,
+    pub(crate) dl_type: &'a str,
+    pub(crate) site: &'a str,
+    pub(crate) bytes: i64,
+    pub(crate) duration_ms: i64,
+    pub(crate) img_count: i32,
+    pub(crate) error_count: i32,
+    pub(crate) created_at: &'a str,
+}
+
+// 一次下载中每个图片域名的请求数、失败数和耗时
+#[derive(Insertable, PartialEq, Debug, Clone)]
+#[diesel(table_name = host_history)]
+pub struct NewHostHistory<'a> {
+    pub(crate) task_id: i32,
+    pub(crate) host: &'a str,
+    pub(crate) requests: i32,
+    pub(crate) failures: i32,
+    pub(crate) bytes: i64,
+    pub(crate) duration_ms: i64,
+    pub(crate) created_at: &'a str,
+}
+
+// 按天或按周汇总的下载量
+#[derive(QueryableByName, Debug, Clone, Serialize)]
+pub struct HistoryTotal {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub period: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub added: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub finished: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub failed: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub bytes: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub img_count: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub duration_ms: i64,
+}
+
+// 按站点汇总的下载量
+#[derive(QueryableByName, Debug, Clone, Serialize)]
+pub struct SiteTotal {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub site: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub tasks: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub finished: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub failed: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub bytes: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub img_count: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub error_count: i64,
+}
+
+// 图片域名的平均耗时和失败率
+#[derive(QueryableByName, Debug, Clone, Serialize)]
+pub struct HostTotal {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub host: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub requests: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub failures: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub bytes: i64,
+    #[diesel(sql_type = diesel::sql_types::Double)]
+    pub avg_ms: f64,
+    #[diesel(sql_type = diesel::sql_types::Double)]
+    pub failure_rate: f64,
 }
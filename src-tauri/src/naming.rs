@@ -0,0 +1,480 @@
+use crate::db::{get_chapter_names, get_done_page_files, update_page_file_paths};
+use crate::models::DownloadTask;
+use crate::utils::{get_second_level_domain, sanitize_name, unique_names, NameRules};
+use crate::SETTING;
+use log::{error, info};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 默认的目录结构，和旧版本相同，作者为空时省略作者目录
+pub const DEFAULT_TEMPLATE: &str = "{author}/{series}_{category}/{chapter}/{page}";
+// 当前章节（current）任务的默认目录结构
+pub const DEFAULT_CURRENT_TEMPLATE: &str = "{series}/{page}";
+
+// 模板中的变量值，数字可以用 {page:03} 补 0
+enum Value {
+    Text(String),
+    Number(usize),
+}
+
+// 重命名的结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RenameResult {
+    pub moved: usize,
+    // 路径没有变化
+    pub unchanged: usize,
+    // 原文件不存在
+    pub missing: usize,
+    // 目标文件已存在，不覆盖
+    pub conflict: usize,
+    // 不在下载目录中（例如导入的漫画），保持原位置
+    pub outside: usize,
+    pub failed: usize,
+}
+
+impl RenameResult {
+    fn add(&mut self, other: &RenameResult) {
+        self.moved += other.moved;
+        self.unchanged += other.unchanged;
+        self.missing += other.missing;
+        self.conflict += other.conflict;
+        self.outside += other.outside;
+        self.failed += other.failed;
+    }
+}
+
+// 单行本 单话 番外篇 或类别的原始标题
+pub fn comic_type(task: &DownloadTask) -> String {
+    match task.dl_type.as_str() {
+        "juan" => String::from("单行本"),
+        "hua" => String::from("单话"),
+        "fanwai" => String::from("番外篇"),
+        "category" => task.category.clone(),
+        "current" => String::from("current"),
+        _ => String::from(""),
+    }
+}
+
+pub fn default_template(dl_type: &str) -> &'static str {
+    if dl_type == "current" {
+        DEFAULT_CURRENT_TEMPLATE
+    } else {
+        DEFAULT_TEMPLATE
+    }
+}
+
+// 设置中的模板，为空或无效时使用默认目录结构
+pub fn task_template(dl_type: &str) -> String {
+    let template = SETTING.read().unwrap().name_template.clone();
+    if template.trim().is_empty() {
+        return String::from(default_template(dl_type));
+    }
+    match validate_template_for(&template, dl_type) {
+        Ok(_) => template,
+        Err(e) => {
+            error!("invalid name_template: {} e: {}", &template, e);
+            String::from(default_template(dl_type))
+        }
+    }
+}
+
+// 设置中的文件名规则，默认按 windows 规则，保存到 exFAT NTFS 磁盘也不会失败
+pub fn name_rules() -> NameRules {
+    NameRules::from_setting(&SETTING.read().unwrap().filename_rules)
+}
+
+// 任务所有章节的目录名，按 group_index 顺序处理，处理后重名的章节加后缀
+pub fn chapter_dirs(names: &Vec<String>, rules: NameRules) -> Vec<String> {
+    unique_names(names, rules)
+}
+
+// 替换模板中的 {name} 和 {name:03}
+fn render(template: &str, mut value: impl FnMut(&str) -> Option<Value>) -> Result<String, String> {
+    let mut res = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("unclosed '{{' in template: {}", template)),
+        };
+        let (name, spec) = match rest[start + 1..end].split_once(':') {
+            Some((name, spec)) => (name.trim(), spec.trim()),
+            None => (rest[start + 1..end].trim(), ""),
+        };
+        let width = if spec.is_empty() {
+            0
+        } else {
+            spec.parse::<usize>()
+                .map_err(|_| format!("invalid format '{}' of {{{}}}", spec, name))?
+        };
+        match value(name) {
+            Some(Value::Text(text)) => res.push_str(&text),
+            Some(Value::Number(number)) => {
+                res.push_str(&format!("{:0width$}", number, width = width))
+            }
+            None => return Err(format!("unknown variable {{{}}}", name)),
+        }
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+// 检查模板，必须包含 {page}，否则同一章节的图片会互相覆盖
+// 多章节的任务还需要区分章节，{volume} 只在单行本中有值
+fn validate_template_for(template: &str, dl_type: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Ok(());
+    }
+    let mut vars: HashSet<String> = HashSet::new();
+    render(template, |name| {
+        let value = match name {
+            "site" | "author" | "series" | "category" | "volume" | "chapter" => {
+                Value::Text(String::new())
+            }
+            "chapter_index" | "page" => Value::Number(0),
+            _ => return None,
+        };
+        vars.insert(name.to_string());
+        Some(value)
+    })?;
+    if !vars.contains("page") {
+        return Err(String::from("template must contain {page}"));
+    }
+    let chapter_vars: &[&str] = match dl_type {
+        "current" => &[],
+        "juan" => &["chapter", "chapter_index", "volume"],
+        _ => &["chapter", "chapter_index"],
+    };
+    if !chapter_vars.is_empty() && !chapter_vars.iter().any(|x| vars.contains(*x)) {
+        return Err(format!(
+            "template must contain {} for {} tasks",
+            chapter_vars
+                .iter()
+                .map(|x| format!("{{{}}}", x))
+                .collect::<Vec<_>>()
+                .join(" or "),
+            dl_type
+        ));
+    }
+    Ok(())
+}
+
+// 设置中的模板用于所有类型的任务
+pub fn validate_template(template: &str) -> Result<(), String> {
+    for dl_type in ["current", "juan", "hua", "fanwai", "category"] {
+        validate_template_for(template, dl_type)?;
+    }
+    Ok(())
+}
+
+// 变量值中的路径分隔符不能生成新的目录
+fn path_value(value: &str) -> String {
+    value.replace(&['/', '\\'][..], "_")
+}
+
+// 图片相对下载目录的路径，模板中的 / 分隔目录，空的目录会被省略
+// {volume} 只在单行本中有值，{chapter_index} 和 {page} 从 0 开始
+// rules 为 None 时不处理文件名，用于查找旧版本保存的图片
+pub fn page_file(
+    task: &DownloadTask,
+    template: &str,
+    group_index: usize,
+    chapter: &str,
+    page: usize,
+    rules: Option<NameRules>,
+) -> Result<PathBuf, String> {
+    let rendered = render(template, |name| match name {
+        "site" => Some(Value::Text(path_value(
+            &get_second_level_domain(&task.url).unwrap_or_default(),
+        ))),
+        "author" => Some(Value::Text(path_value(&task.author))),
+        "series" => Some(Value::Text(path_value(&task.comic_name))),
+        "category" => Some(Value::Text(path_value(&comic_type(task)))),
+        "volume" => Some(Value::Text(if task.dl_type == "juan" {
+            path_value(chapter)
+        } else {
+            String::new()
+        })),
+        "chapter" => Some(Value::Text(path_value(chapter))),
+        "chapter_index" => Some(Value::Number(group_index)),
+        "page" => Some(Value::Number(page)),
+        _ => None,
+    })?;
+    let mut parts: Vec<String> = rendered
+        .split(&['/', '\\'][..])
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+        .map(|part| match rules {
+            Some(rules) => sanitize_name(part, rules),
+            None => part.to_string(),
+        })
+        .collect();
+    match parts.last_mut() {
+        Some(file_name) => file_name.push_str(".jpg"),
+        None => return Err(format!("empty path from template: {}", template)),
+    }
+    Ok(parts.iter().collect())
+}
+
+// 先尝试 rename，跨磁盘时复制并检查大小后删除原文件
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_err() {
+        let copied = fs::copy(from, to)?;
+        if copied != fs::metadata(from)?.len() || copied != fs::metadata(to)?.len() {
+            let _ = fs::remove_file(to);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "copied file size mismatch",
+            ));
+        }
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+// 删除移动后留下的空目录，直到下载目录为止
+fn remove_empty_dirs(dir: &Path, root: &Path) {
+    let mut dir = dir.to_path_buf();
+    while dir.starts_with(root) && dir != root {
+        if fs::remove_dir(&dir).is_err() {
+            break;
+        }
+        dir = match dir.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => break,
+        };
+    }
+}
+
+// 任务已下载图片的当前路径，以及处理后的章节目录名
+struct PageFile {
+    group_index: i32,
+    page_index: i32,
+    chapter_dir: String,
+    path: PathBuf,
+}
+
+// 查询任务已下载的图片，旧版本下载的图片没有记录路径，按默认目录结构在 download_dir 中查找
+fn task_page_files(task: &DownloadTask, download_dir: &Path) -> Result<Vec<PageFile>, String> {
+    let files = get_done_page_files(task.id).map_err(|e| e.to_string())?;
+    let chapters = get_chapter_names(task.id).map_err(|e| e.to_string())?;
+    let dirs: HashMap<i32, String> = chapters
+        .iter()
+        .map(|(group_index, _)| *group_index)
+        .zip(chapter_dirs(
+            &chapters.iter().map(|(_, name)| name.clone()).collect(),
+            name_rules(),
+        ))
+        .collect();
+    let legacy_template = default_template(&task.dl_type);
+    let mut res = Vec::new();
+    for (group_index, page_index, chapter, file_path) in files.into_iter() {
+        let path = if file_path.is_empty() {
+            download_dir.join(page_file(
+                task,
+                legacy_template,
+                group_index as usize,
+                &chapter,
+                page_index as usize,
+                None,
+            )?)
+        } else {
+            PathBuf::from(file_path)
+        };
+        res.push(PageFile {
+            group_index,
+            page_index,
+            chapter_dir: dirs.get(&group_index).cloned().unwrap_or(chapter),
+            path,
+        });
+    }
+    Ok(res)
+}
+
+// 把任务已下载的图片移动到 new_path 返回的路径，并更新 pages 表中的路径
+// 不在 download_dir 中的图片不移动，每处理一张图片调用一次 on_file
+fn move_task_files(
+    task: &DownloadTask,
+    download_dir: &Path,
+    new_path: impl Fn(&PageFile) -> Result<PathBuf, String>,
+    on_file: &mut impl FnMut(),
+) -> RenameResult {
+    let mut result = RenameResult::default();
+    let files = match task_page_files(task, download_dir) {
+        Ok(files) => files,
+        Err(e) => {
+            error!("task_page_files failed: {} e: {}", task.id, e);
+            result.failed += 1;
+            return result;
+        }
+    };
+    let mut new_paths: Vec<(i32, i32, String)> = Vec::new();
+    let mut old_dirs: HashSet<PathBuf> = HashSet::new();
+    for file in files.iter() {
+        on_file();
+        let old = &file.path;
+        if !old.starts_with(download_dir) {
+            result.outside += 1;
+            continue;
+        }
+        let new = match new_path(file) {
+            Ok(path) => path,
+            Err(e) => {
+                error!("move_task_files new path failed: {} e: {}", task.id, e);
+                result.failed += 1;
+                continue;
+            }
+        };
+        if *old == new {
+            result.unchanged += 1;
+        } else if !old.exists() {
+            result.missing += 1;
+            continue;
+        } else if new.exists() {
+            error!(
+                "move_task_files target exists: {} -> {}",
+                old.display(),
+                new.display()
+            );
+            result.conflict += 1;
+            continue;
+        } else if let Err(e) = move_file(old, &new) {
+            error!(
+                "move_task_files move failed: {} -> {} e: {}",
+                old.display(),
+                new.display(),
+                e
+            );
+            result.failed += 1;
+            continue;
+        } else {
+            result.moved += 1;
+            if let Some(parent) = old.parent() {
+                old_dirs.insert(parent.to_path_buf());
+            }
+        }
+        new_paths.push((
+            file.group_index,
+            file.page_index,
+            new.to_string_lossy().to_string(),
+        ));
+    }
+    if let Err(e) = update_page_file_paths(task.id, &new_paths) {
+        error!("update_page_file_paths failed: {} e: {}", task.id, e);
+    }
+    for dir in old_dirs.iter() {
+        remove_empty_dirs(dir, download_dir);
+    }
+    info!("move_task_files id: {} result: {:?}", task.id, &result);
+    result
+}
+
+// 按新的模板重命名所有任务的图片
+pub fn rename_all(tasks: &Vec<DownloadTask>, template: &str, download_dir: &Path) -> RenameResult {
+    let rules = name_rules();
+    let mut result = RenameResult::default();
+    for task in tasks.iter() {
+        let template = if template.trim().is_empty() {
+            default_template(&task.dl_type)
+        } else {
+            template
+        };
+        let new_path = |file: &PageFile| {
+            page_file(
+                task,
+                template,
+                file.group_index as usize,
+                &file.chapter_dir,
+                file.page_index as usize,
+                Some(rules),
+            )
+            .map(|path| download_dir.join(path))
+        };
+        result.add(&move_task_files(task, download_dir, new_path, &mut || {}));
+    }
+    result
+}
+
+// 所有任务已下载的图片数，用于显示移动进度
+pub fn count_library_files(tasks: &Vec<DownloadTask>, download_dir: &Path) -> usize {
+    tasks
+        .iter()
+        .map(|task| {
+            task_page_files(task, download_dir)
+                .map(|x| x.len())
+                .unwrap_or(0)
+        })
+        .sum()
+}
+
+// 把所有任务的图片从 old_dir 移动到 new_dir，保持相对路径不变
+// 不在 old_dir 中的图片保持原位置，on_file 的参数为已处理的图片数
+pub fn relocate_all(
+    tasks: &Vec<DownloadTask>,
+    old_dir: &Path,
+    new_dir: &Path,
+    on_file: &mut impl FnMut(usize),
+) -> RenameResult {
+    let mut result = RenameResult::default();
+    let mut done = 0;
+    for task in tasks.iter() {
+        let new_path = |file: &PageFile| {
+            file.path
+                .strip_prefix(old_dir)
+                .map(|relative| new_dir.join(relative))
+                .map_err(|e| e.to_string())
+        };
+        result.add(&move_task_files(task, old_dir, new_path, &mut || {
+            done += 1;
+            on_file(done);
+        }));
+    }
+    result
+}
+
+// 任务的目录，为所有图片所在目录的公共上级目录，还没有下载的图片时按当前模板计算
+pub fn task_dir(task: &DownloadTask, download_dir: &Path) -> PathBuf {
+    let mut paths: Vec<PathBuf> = task_page_files(task, download_dir)
+        .map(|files| files.into_iter().map(|x| x.path).collect())
+        .unwrap_or_default();
+    if paths.is_empty() {
+        let template = task_template(&task.dl_type);
+        let chapters = get_chapter_names(task.id).unwrap_or_default();
+        let dirs = chapter_dirs(
+            &chapters.iter().map(|(_, name)| name.clone()).collect(),
+            name_rules(),
+        );
+        for ((group_index, _), dir) in chapters.iter().zip(dirs.iter()) {
+            if let Ok(path) = page_file(
+                task,
+                &template,
+                *group_index as usize,
+                dir,
+                0,
+                Some(name_rules()),
+            ) {
+                paths.push(download_dir.join(path));
+            }
+        }
+    }
+    let mut dir = match paths.first().and_then(|x| x.parent()) {
+        Some(dir) => dir.to_path_buf(),
+        None => return download_dir.to_path_buf(),
+    };
+    for path in paths.iter() {
+        while !path.starts_with(&dir) {
+            dir = match dir.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return download_dir.to_path_buf(),
+            };
+        }
+    }
+    dir
+}
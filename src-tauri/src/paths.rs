@@ -0,0 +1,171 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+// 旧版本和非 Linux 系统的数据目录 ~/.comic_dl_tauri
+const LEGACY_DIR: &str = ".comic_dl_tauri";
+// XDG 目录和便携模式下使用的目录名
+const APP_DIR: &str = "comic_dl_tauri";
+// 指定数据根目录的环境变量和启动参数，设置、数据库、缓存和日志都放在这个目录下
+const HOME_ENV: &str = "COMIC_DL_HOME";
+const HOME_FLAG: &str = "--data-dir";
+// 便携模式，数据保存在程序旁的 comic_dl_tauri 目录，程序旁有 portable 文件时同样开启
+const PORTABLE_FLAG: &str = "--portable";
+const PORTABLE_MARKER: &str = "portable";
+// 单独指定数据库、缓存、日志目录，可以放在不同的磁盘
+const DATA_ENV: &str = "COMIC_DL_DATA_DIR";
+const CACHE_ENV: &str = "COMIC_DL_CACHE_DIR";
+const LOG_ENV: &str = "COMIC_DL_LOG_DIR";
+
+// 程序使用的所有目录，启动时确定一次
+#[derive(Debug, Clone)]
+pub struct AppPaths {
+    // setting.json
+    pub config_dir: PathBuf,
+    // 数据库、备份、默认下载目录
+    pub data_dir: PathBuf,
+    // html_cache json_cache
+    pub cache_dir: PathBuf,
+    // 日志
+    pub log_dir: PathBuf,
+}
+
+impl AppPaths {
+    // 所有内容放在同一个目录下，和旧版本的结构相同
+    fn single(root: PathBuf) -> Self {
+        AppPaths {
+            config_dir: root.clone(),
+            data_dir: root.clone(),
+            cache_dir: root.clone(),
+            log_dir: root.join("log"),
+        }
+    }
+
+    pub fn setting_file(&self) -> PathBuf {
+        self.config_dir.join("setting.json")
+    }
+
+    pub fn db_file(&self) -> PathBuf {
+        self.data_dir.join("db/db.sqlite")
+    }
+
+    pub fn backup_dir(&self) -> PathBuf {
+        self.data_dir.join("backup")
+    }
+
+    pub fn default_download_dir(&self) -> PathBuf {
+        self.data_dir.join("download")
+    }
+
+    pub fn html_cache_dir(&self) -> PathBuf {
+        self.cache_dir.join("html_cache")
+    }
+
+    pub fn json_cache_dir(&self) -> PathBuf {
+        self.cache_dir.join("json_cache")
+    }
+
+    // 启动时需要创建的目录
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        vec![
+            self.config_dir.clone(),
+            self.data_dir.clone(),
+            self.html_cache_dir(),
+            self.json_cache_dir(),
+            self.log_dir.clone(),
+        ]
+    }
+}
+
+pub static APP_PATHS: LazyLock<AppPaths> = LazyLock::new(resolve);
+
+pub fn app_paths() -> &'static AppPaths {
+    &APP_PATHS
+}
+
+// --data-dir <path> 或 --data-dir=<path>
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    let prefix = format!("{}=", flag);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == flag {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(&prefix).map(String::from)
+        }
+    })
+}
+
+// 只接受绝对路径，相对路径按当前目录转换
+fn absolute(path: &str) -> Option<PathBuf> {
+    let path = path.trim();
+    if path.is_empty() {
+        return None;
+    }
+    let path = PathBuf::from(path);
+    if path.is_absolute() {
+        Some(path)
+    } else {
+        env::current_dir().ok().map(|x| x.join(path))
+    }
+}
+
+fn env_path(key: &str) -> Option<PathBuf> {
+    env::var(key).ok().and_then(|x| absolute(&x))
+}
+
+// XDG 规范要求环境变量中的路径为绝对路径，否则使用默认值
+fn xdg_dir(key: &str, home: &Path, default: &str) -> PathBuf {
+    env::var(key)
+        .ok()
+        .map(PathBuf::from)
+        .filter(|x| x.is_absolute())
+        .unwrap_or_else(|| home.join(default))
+        .join(APP_DIR)
+}
+
+fn exe_dir() -> Option<PathBuf> {
+    env::current_exe()
+        .ok()
+        .and_then(|x| x.parent().map(Path::to_path_buf))
+}
+
+// 按顺序：启动参数、环境变量、便携模式、已有的 ~/.comic_dl_tauri、Linux 的 XDG 目录、~/.comic_dl_tauri
+// 最后再用 COMIC_DL_DATA_DIR 等环境变量单独覆盖数据库、缓存、日志目录
+fn resolve() -> AppPaths {
+    let args: Vec<String> = env::args().collect();
+    let mut paths = if let Some(root) = flag_value(&args, HOME_FLAG).and_then(|x| absolute(&x)) {
+        AppPaths::single(root)
+    } else if let Some(root) = env_path(HOME_ENV) {
+        AppPaths::single(root)
+    } else if let Some(exe_dir) = exe_dir()
+        .filter(|dir| args.iter().any(|x| x == PORTABLE_FLAG) || dir.join(PORTABLE_MARKER).exists())
+    {
+        AppPaths::single(exe_dir.join(APP_DIR))
+    } else {
+        // 没有用户目录时使用程序所在目录
+        let home = home::home_dir()
+            .or_else(exe_dir)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let legacy = home.join(LEGACY_DIR);
+        if legacy.exists() || !cfg!(target_os = "linux") {
+            AppPaths::single(legacy)
+        } else {
+            AppPaths {
+                config_dir: xdg_dir("XDG_CONFIG_HOME", &home, ".config"),
+                data_dir: xdg_dir("XDG_DATA_HOME", &home, ".local/share"),
+                cache_dir: xdg_dir("XDG_CACHE_HOME", &home, ".cache"),
+                log_dir: xdg_dir("XDG_STATE_HOME", &home, ".local/state").join("log"),
+            }
+        }
+    };
+    if let Some(dir) = env_path(DATA_ENV) {
+        paths.data_dir = dir;
+    }
+    if let Some(dir) = env_path(CACHE_ENV) {
+        paths.cache_dir = dir;
+    }
+    if let Some(dir) = env_path(LOG_ENV) {
+        paths.log_dir = dir;
+    }
+    paths
+}
@@ -13,5 +13,87 @@ diesel::table! {
       now_count -> Integer,
       error_vec -> Text,
       done -> Bool,
+      category -> Text,
+      author_id -> Integer,
+      weight -> Integer,
+      queue_position -> Integer,
+      pause_reason -> Text,
+  }
+}
+
+diesel::table! {
+  authors {
+      id -> Integer,
+      name -> Text,
+      url -> Text,
+      comic_count -> Integer,
+      updated_at -> Text,
+  }
+}
+
+diesel::table! {
+  series {
+      id -> Integer,
+      url -> Text,
+      comic_name -> Text,
+      author -> Text,
+      author_id -> Integer,
+  }
+}
+
+diesel::table! {
+  chapters {
+      id -> Integer,
+      series_id -> Integer,
+      task_id -> Integer,
+      group_index -> Integer,
+      name -> Text,
+      href -> Text,
+      count -> Integer,
+  }
+}
+
+diesel::table! {
+  pages {
+      id -> Integer,
+      chapter_id -> Integer,
+      task_id -> Integer,
+      group_index -> Integer,
+      page_index -> Integer,
+      href -> Text,
+      alt_href -> Nullable<Text>,
+      done -> Bool,
+      error_msg -> Text,
+      file_path -> Text,
+  }
+}
+
+diesel::table! {
+  task_history {
+      id -> Integer,
+      task_id -> Integer,
+      event -> Text,
+      comic_name -> Text,
+      author -> Text,
+      dl_type -> Text,
+      site -> Text,
+      bytes -> BigInt,
+      duration_ms -> BigInt,
+      img_count -> Integer,
+      error_count -> Integer,
+      created_at -> Text,
+  }
+}
+
+diesel::table! {
+  host_history {
+      id -> Integer,
+      task_id -> Integer,
+      host -> Text,
+      requests -> Integer,
+      failures -> Integer,
+      bytes -> BigInt,
+      duration_ms -> BigInt,
+      created_at -> Text,
   }
 }
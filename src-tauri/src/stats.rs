@@ -0,0 +1,212 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+// 计算速度的滑动窗口
+const WINDOW: Duration = Duration::from_secs(5);
+
+// 下载的字节数和最近一段时间的速度
+struct Meter {
+    started: Instant,
+    total: u64,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Meter {
+    fn new() -> Self {
+        Meter {
+            started: Instant::now(),
+            total: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn add(&mut self, bytes: u64) {
+        let now = Instant::now();
+        self.total += bytes;
+        self.samples.push_back((now, bytes));
+        self.prune(now);
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((time, _)) = self.samples.front() {
+            if now.duration_since(*time) > WINDOW {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // 每秒字节数，刚开始不满一个窗口时按实际经过的时间计算
+    fn speed(&mut self) -> u64 {
+        let now = Instant::now();
+        self.prune(now);
+        let bytes: u64 = self.samples.iter().map(|(_, b)| b).sum();
+        let secs = now
+            .duration_since(self.started)
+            .min(WINDOW)
+            .as_secs_f64()
+            .max(0.5);
+        (bytes as f64 / secs) as u64
+    }
+}
+
+struct TaskStats {
+    meter: Meter,
+    total: i32,
+    // 已完成的图片数，和任务协程共用
+    progress: Arc<AtomicUsize>,
+    // 本次运行下载完成的图片数，用来估算每张图片的大小
+    downloaded: u64,
+    hosts: HashMap<String, HostCounter>,
+}
+
+// 一次下载中单个图片域名的请求统计
+#[derive(Debug, Clone, Default)]
+pub struct HostCounter {
+    pub requests: u32,
+    pub failures: u32,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+// 一次下载结束时写入历史的统计
+#[derive(Debug, Clone, Default)]
+pub struct RunStats {
+    pub bytes: u64,
+    pub duration: Duration,
+    pub downloaded: u64,
+    pub hosts: Vec<(String, HostCounter)>,
+}
+
+struct Stats {
+    tasks: HashMap<i32, TaskStats>,
+    global: Meter,
+}
+
+static STATS: LazyLock<Mutex<Stats>> = LazyLock::new(|| {
+    Mutex::new(Stats {
+        tasks: HashMap::new(),
+        global: Meter::new(),
+    })
+});
+
+// 任务的速度、本次下载的字节数和剩余时间
+#[derive(Debug, Clone)]
+pub struct TaskSpeed {
+    pub now_count: usize,
+    pub total: i32,
+    pub speed: u64,
+    pub bytes: u64,
+    // 剩余秒数，无法估算时为 -1
+    pub eta: i64,
+}
+
+pub fn start_task_stats(id: i32, total: i32, progress: Arc<AtomicUsize>) {
+    STATS.lock().unwrap().tasks.insert(
+        id,
+        TaskStats {
+            meter: Meter::new(),
+            total,
+            progress,
+            downloaded: 0,
+            hosts: HashMap::new(),
+        },
+    );
+}
+
+pub fn end_task_stats(id: i32) {
+    STATS.lock().unwrap().tasks.remove(&id);
+}
+
+pub fn task_bytes(id: i32) -> u64 {
+    STATS
+        .lock()
+        .unwrap()
+        .tasks
+        .get(&id)
+        .map(|x| x.meter.total)
+        .unwrap_or(0)
+}
+
+pub fn record_bytes(id: i32, bytes: usize) {
+    let mut stats = STATS.lock().unwrap();
+    stats.global.add(bytes as u64);
+    if let Some(task) = stats.tasks.get_mut(&id) {
+        task.meter.add(bytes as u64);
+    }
+}
+
+pub fn record_img_done(id: i32) {
+    if let Some(task) = STATS.lock().unwrap().tasks.get_mut(&id) {
+        task.downloaded += 1;
+    }
+}
+
+// 记录一次图片请求的结果，bytes 为成功时的图片大小
+pub fn record_request(id: i32, host: &str, ok: bool, duration: Duration, bytes: usize) {
+    if let Some(task) = STATS.lock().unwrap().tasks.get_mut(&id) {
+        let counter = task.hosts.entry(host.to_string()).or_default();
+        counter.requests += 1;
+        if !ok {
+            counter.failures += 1;
+        }
+        counter.bytes += bytes as u64;
+        counter.duration += duration;
+    }
+}
+
+pub fn run_stats(id: i32) -> RunStats {
+    match STATS.lock().unwrap().tasks.get(&id) {
+        Some(task) => RunStats {
+            bytes: task.meter.total,
+            duration: task.meter.started.elapsed(),
+            downloaded: task.downloaded,
+            hosts: task
+                .hosts
+                .iter()
+                .map(|(host, counter)| (host.clone(), counter.clone()))
+                .collect(),
+        },
+        None => RunStats::default(),
+    }
+}
+
+pub fn task_speeds() -> Vec<(i32, TaskSpeed)> {
+    let mut stats = STATS.lock().unwrap();
+    stats
+        .tasks
+        .iter_mut()
+        .map(|(id, task)| {
+            let now_count = task.progress.load(Ordering::Relaxed);
+            let speed = task.meter.speed();
+            let remaining = (task.total as u64).saturating_sub(now_count as u64);
+            let eta = if remaining == 0 {
+                0
+            } else if speed == 0 || task.downloaded == 0 {
+                -1
+            } else {
+                let avg_bytes = task.meter.total / task.downloaded;
+                (remaining * avg_bytes / speed) as i64
+            };
+            (
+                *id,
+                TaskSpeed {
+                    now_count,
+                    total: task.total,
+                    speed,
+                    bytes: task.meter.total,
+                    eta,
+                },
+            )
+        })
+        .collect()
+}
+
+// 所有任务的速度和启动以来下载的字节数
+pub fn global_speed() -> (u64, u64) {
+    let mut stats = STATS.lock().unwrap();
+    (stats.global.speed(), stats.global.total)
+}
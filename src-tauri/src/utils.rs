@@ -1,7 +1,9 @@
+use crate::paths::app_paths;
 #[allow(dead_code)]
 use image::ImageFormat;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt;
 use std::fs::{self, File};
 use std::io::Error;
@@ -135,8 +137,228 @@ pub fn extract_number_manual(input: &str) -> Option<u32> {
     }
 }
 
+// 章节排序用的 key，依次比较 是否番外、卷号、话数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChapterKey {
+    pub extra: bool,
+    pub volume: Option<f64>,
+    pub chapter: Option<f64>,
+}
+
+impl ChapterKey {
+    // 卷号和话数分开给出时使用，例如 mangadex 的 volume 和 chapter 字段
+    pub fn from_pair(volume: &str, chapter: &str) -> ChapterKey {
+        let volume_key = parse_chapter_number(volume);
+        let chapter_key = parse_chapter_number(chapter);
+        ChapterKey {
+            extra: chapter_key.extra,
+            volume: volume_key.number(),
+            chapter: chapter_key.number(),
+        }
+    }
+
+    // 用于范围选择的编号，没有话数时使用卷号
+    pub fn number(&self) -> Option<f64> {
+        self.chapter.or(self.volume)
+    }
+}
+
+impl Eq for ChapterKey {}
+
+impl PartialOrd for ChapterKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ChapterKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        fn cmp_option(a: Option<f64>, b: Option<f64>) -> std::cmp::Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            }
+        }
+        self.extra
+            .cmp(&other.extra)
+            .then_with(|| cmp_option(self.volume, other.volume))
+            .then_with(|| cmp_option(self.chapter, other.chapter))
+    }
+}
+
+const EXTRA_KEYWORDS: [&str; 6] = ["番外", "特别", "特別", "外传", "外傳", "extra"];
+const VOLUME_SUFFIXES: [char; 4] = ['卷', '册', '冊', '部'];
+const VOLUME_PREFIXES: [&str; 3] = ["vol", "volume", "v"];
+const CHAPTER_SUFFIXES: [char; 5] = ['话', '話', '回', '章', '集'];
+const CHAPTER_PREFIXES: [&str; 4] = ["ch", "chapter", "ep", "episode"];
+
+// 前缀以某个单词结尾，且单词前面不是字母，避免 v 匹配到 rev 之类的单词
+fn ends_with_word(prefix: &str, word: &str) -> bool {
+    prefix
+        .strip_suffix(word)
+        .is_some_and(|rest| !rest.chars().last().is_some_and(|c| c.is_ascii_alphabetic()))
+}
+
+// 解析章节名中的卷号和话数，支持小数、中文数字、全角数字，例如 第10.5话 第十二话 第2卷第3话 Vol.2 Ch.10.5
+pub fn parse_chapter_number(input: &str) -> ChapterKey {
+    let normalized: String = input
+        .chars()
+        .map(|c| match c {
+            '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32).unwrap_or(c),
+            '．' => '.',
+            _ => c,
+        })
+        .collect::<String>()
+        .to_lowercase();
+    let extra = EXTRA_KEYWORDS.iter().any(|k| normalized.contains(k));
+
+    let chars: Vec<char> = normalized.chars().collect();
+    let mut volume: Option<f64> = None;
+    let mut chapter: Option<f64> = None;
+    let mut unmarked: Option<f64> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let number = if chars[i].is_ascii_digit() {
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || (chars[i] == '.'
+                        && i + 1 < chars.len()
+                        && chars[i + 1].is_ascii_digit()
+                        && !chars[start..i].contains(&'.')))
+            {
+                i += 1;
+            }
+            chars[start..i]
+                .iter()
+                .collect::<String>()
+                .parse::<f64>()
+                .ok()
+        } else if is_chinese_numeral(chars[i]) {
+            while i < chars.len() && is_chinese_numeral(chars[i]) {
+                i += 1;
+            }
+            chinese_numeral_to_number(&chars[start..i].iter().collect::<String>())
+        } else {
+            i += 1;
+            continue;
+        };
+
+        let Some(number) = number else {
+            continue;
+        };
+        let prefix: String = chars[..start].iter().collect::<String>();
+        let prefix = prefix.trim_end_matches(&['.', ' ', '_'][..]);
+        let next = chars.get(i).copied();
+        let is_volume = next.is_some_and(|c| VOLUME_SUFFIXES.contains(&c))
+            || VOLUME_PREFIXES.iter().any(|p| ends_with_word(prefix, p));
+        // 带有 第/话/ch 等标记的数字才确定是话数，没有标记的只在找不到标记时使用
+        let is_chapter = next.is_some_and(|c| CHAPTER_SUFFIXES.contains(&c))
+            || prefix.ends_with('第')
+            || CHAPTER_PREFIXES.iter().any(|p| ends_with_word(prefix, p));
+        if is_volume {
+            volume = volume.or(Some(number));
+        } else if is_chapter {
+            chapter = chapter.or(Some(number));
+        } else {
+            unmarked = unmarked.or(Some(number));
+        }
+    }
+
+    ChapterKey {
+        extra,
+        volume,
+        chapter: chapter.or(unmarked),
+    }
+}
+
+fn is_chinese_numeral(c: char) -> bool {
+    "零〇一二两兩三四五六七八九十百千万萬".contains(c)
+}
+
+// 中文数字转数字，例如 十二 -> 12，一百零五 -> 105，二〇一九 -> 2019
+fn chinese_numeral_to_number(input: &str) -> Option<f64> {
+    fn digit(c: char) -> Option<u64> {
+        match c {
+            '零' | '〇' => Some(0),
+            '一' => Some(1),
+            '二' | '两' | '兩' => Some(2),
+            '三' => Some(3),
+            '四' => Some(4),
+            '五' => Some(5),
+            '六' => Some(6),
+            '七' => Some(7),
+            '八' => Some(8),
+            '九' => Some(9),
+            _ => None,
+        }
+    }
+
+    // 单独的 百 千 万 多半是名字里的字，不当作数字
+    if !input.starts_with('十') && !input.chars().any(|c| digit(c).is_some()) {
+        return None;
+    }
+    let has_unit = input.chars().any(|c| "十百千万萬".contains(c));
+    if !has_unit {
+        // 逐位读法
+        let mut res: u64 = 0;
+        for c in input.chars() {
+            res = res * 10 + digit(c)?;
+        }
+        return Some(res as f64);
+    }
+
+    let mut total: u64 = 0;
+    let mut section: u64 = 0;
+    let mut current: Option<u64> = None;
+    for c in input.chars() {
+        if let Some(d) = digit(c) {
+            current = Some(d);
+            continue;
+        }
+        let unit = match c {
+            '十' => 10,
+            '百' => 100,
+            '千' => 1000,
+            '万' | '萬' => {
+                section += current.take().unwrap_or(0);
+                total += section.max(1) * 10000;
+                section = 0;
+                continue;
+            }
+            _ => return None,
+        };
+        // 十二 省略了开头的 一
+        section += current.take().unwrap_or(1) * unit;
+    }
+    section += current.unwrap_or(0);
+    Some((total + section) as f64)
+}
+
+// 解析章节范围字符串，例如 "1-20,35,40-"，返回 (起始, 结束) 列表，结束为 None 表示不限
+pub fn parse_number_ranges(input: &str) -> Vec<(f64, Option<f64>)> {
+    let mut res = Vec::new();
+    for part in input.split(&[',', '，'][..]) {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        if let Some((start, end)) = part.split_once('-') {
+            let start = start.trim().parse::<f64>().unwrap_or(0.0);
+            let end = end.trim();
+            if end.is_empty() {
+                res.push((start, None));
+            } else if let Ok(end) = end.parse::<f64>() {
+                res.push((start, Some(end)));
+            }
+        } else if let Ok(number) = part.parse::<f64>() {
+            res.push((number, Some(number)));
+        }
+    }
+    res
+}
+
 // 获取本地目录的名称
-#[allow(dead_code)]
 pub fn get_dir_name<P: AsRef<Path>>(path: P) -> Option<String> {
     let path = path.as_ref();
     path.file_name()
@@ -144,7 +366,6 @@ pub fn get_dir_name<P: AsRef<Path>>(path: P) -> Option<String> {
 }
 
 // 判断一个路径是否是图片文件
-#[allow(dead_code)]
 pub fn is_image_file(path: &Path) -> bool {
     match path.extension().and_then(|s| s.to_str()) {
         Some(ext) => matches!(
@@ -156,7 +377,6 @@ pub fn is_image_file(path: &Path) -> bool {
 }
 
 // 获取不带扩展名的文件名
-#[allow(dead_code)]
 pub fn get_file_name_without_extension(path: &Path) -> Option<String> {
     path.file_stem()
         .and_then(|s| s.to_str())
@@ -216,81 +436,39 @@ pub fn get_url_query(url_str: String, key: String) -> String {
     String::from("")
 }
 
+// 设置url中的query参数，已存在时替换
+pub fn set_url_query(url_str: &str, key: &str, value: &str) -> String {
+    match Url::parse(url_str) {
+        Ok(mut url) => {
+            let pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(k, _)| k != key)
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            url.query_pairs_mut()
+                .clear()
+                .extend_pairs(pairs)
+                .append_pair(key, value);
+            url.to_string()
+        }
+        Err(_) => url_str.to_string(),
+    }
+}
+
 pub fn create_cache_dir() -> Result<StatusCode, ErrorMsg> {
     info!("create_cache_dir invoke");
-    let home_dir = home::home_dir();
-
-    if let Some(home_dir_temp) = home_dir {
-        let final_path = home_dir_temp.join(".comic_dl_tauri");
-        let html_cache_path = final_path.join("html_cache");
-        let json_cache_path = final_path.join("json_cache");
-        let log_path = final_path.join("log");
-
-        if !final_path.exists() {
-            if let Err(e) = fs::create_dir_all(&final_path) {
-                error!("create_cache_dir final_path error: {e}");
-                return Err(ErrorMsg::new(
-                    format!(
-                        "create {} directory failed!",
-                        &final_path
-                            .file_name()
-                            .and_then(|name| name.to_str())
-                            .unwrap_or(".comic_dl_tauri")
-                    )
-                    .as_str(),
-                ));
-            }
-        }
-        if !html_cache_path.exists() {
-            if let Err(e) = fs::create_dir_all(&html_cache_path) {
-                error!("create_cache_dir html_cache_path error: {e}");
+    // 设置、数据库、缓存和日志目录可能在不同的位置，见 paths.rs
+    for dir in app_paths().dirs() {
+        if !dir.exists() {
+            if let Err(e) = fs::create_dir_all(&dir) {
+                error!("create_cache_dir {} error: {e}", dir.display());
                 return Err(ErrorMsg::new(
-                    format!(
-                        "create {} directory failed!",
-                        &html_cache_path
-                            .file_name()
-                            .and_then(|name| name.to_str())
-                            .unwrap_or("html_cache")
-                    )
-                    .as_str(),
+                    format!("create {} directory failed!", dir.display()).as_str(),
                 ));
             }
         }
-        if !json_cache_path.exists() {
-            if let Err(e) = fs::create_dir_all(&json_cache_path) {
-                error!("create_cache_dir json_cache_path error: {e}");
-                return Err(ErrorMsg::new(
-                    format!(
-                        "create {} directory failed!",
-                        &json_cache_path
-                            .file_name()
-                            .and_then(|name| name.to_str())
-                            .unwrap_or("json_cache")
-                    )
-                    .as_str(),
-                ));
-            }
-        }
-        if !log_path.exists() {
-            if let Err(e) = fs::create_dir_all(&log_path) {
-                error!("create_cache_dir log_path error: {e}");
-                return Err(ErrorMsg::new(
-                    format!(
-                        "create {} directory failed!",
-                        &log_path
-                            .file_name()
-                            .and_then(|name| name.to_str())
-                            .unwrap_or("log")
-                    )
-                    .as_str(),
-                ));
-            }
-        }
-
-        return Ok(StatusCode::Success);
-    } else {
-        return Err(ErrorMsg::new("get home directory failed!"));
     }
+    Ok(StatusCode::Success)
 }
 
 pub fn cache_html(html: &str, name: PathBuf) -> Result<StatusCode, ErrorMsg> {
@@ -334,46 +512,83 @@ where
     Ok(data)
 }
 
-// 下载html重试函数
-pub async fn retry_request(url: &str, max_retries: u32) -> Result<String, reqwest::Error> {
+// 缓存页面时保存的校验信息，下次请求时带上，页面没有变化时服务器返回 304
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    fn from_headers(headers: &reqwest::header::HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|x: &reqwest::header::HeaderValue| x.to_str().ok())
+                .map(String::from)
+        };
+        CacheValidators {
+            etag: get(reqwest::header::ETAG),
+            last_modified: get(reqwest::header::LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+pub enum Fetched {
+    Modified(String, CacheValidators),
+    NotModified,
+}
+
+// 条件请求，带上 If-None-Match / If-Modified-Since，页面没有变化时返回 NotModified
+pub async fn conditional_request(
+    url: &str,
+    validators: &CacheValidators,
+    max_retries: u32,
+) -> Result<Fetched, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
     let mut retries = 0;
 
     loop {
-        // 尝试创建客户端
-        let client_result = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
-            .build();
+        let mut request = client.get(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
 
-        let client = match client_result {
-            Ok(client) => client,
-            Err(err) => {
-                retries += 1;
-                if retries >= max_retries {
-                    return Err(err);
-                }
-                error!(
-                    "Client creation failed (attempt {}): {:?}. Retrying... url: {}",
-                    retries, err, &url
-                );
-                continue;
+        let result = match request.send().await {
+            Ok(response) if response.status() == reqwest::StatusCode::NOT_MODIFIED => {
+                return Ok(Fetched::NotModified);
             }
+            Ok(response) => match response.error_for_status() {
+                Ok(response) => {
+                    let validators = CacheValidators::from_headers(response.headers());
+                    response
+                        .text()
+                        .await
+                        .map(|html| Fetched::Modified(html, validators))
+                }
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
         };
 
-        retries = 0;
-
-        // 发送请求
-        match client.get(url).send().await {
-            Ok(response) => {
-                let html_content = response.text().await?;
-                return Ok(html_content);
-            }
+        match result {
+            Ok(fetched) => return Ok(fetched),
             Err(err) => {
                 retries += 1;
                 if retries >= max_retries {
                     return Err(err);
                 }
                 error!(
-                    "Request failed (attempt {}): {:?}. Retrying... url: {}",
+                    "Conditional request failed (attempt {}): {:?}. Retrying... url: {}",
                     retries, err, &url
                 );
             }
@@ -381,9 +596,148 @@ pub async fn retry_request(url: &str, max_retries: u32) -> Result<String, reqwes
     }
 }
 
+// 作者和漫画名保存到数据库前的处理，按最严格的规则保证在所有系统上都可以作为文件名
 pub fn clean_string(input: &str) -> String {
-    input
-        .replace(" ", "_")
-        .replace(&['/', '\\'][..], "_")
-        .replace(&['!', '?'][..], "")
+    sanitize_name(
+        &input.replace(" ", "_").replace(&['!', '?'][..], ""),
+        NameRules::Windows,
+    )
+}
+
+// 文件名规则，windows 同时适用于 exFAT FAT32 NTFS 格式的磁盘，unix 只限制 / 和长度
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameRules {
+    Windows,
+    Unix,
+}
+
+impl NameRules {
+    pub fn from_setting(value: &str) -> NameRules {
+        match value {
+            "unix" => NameRules::Unix,
+            _ => NameRules::Windows,
+        }
+    }
+}
+
+// 大多数文件系统限制文件名为 255 字节，预留重名后缀和扩展名
+pub const MAX_NAME_BYTES: usize = 200;
+
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// 按字节截断，不截断多字节字符
+fn truncate_bytes(input: &str, max: usize) -> &str {
+    if input.len() <= max {
+        return input;
+    }
+    let mut end = max;
+    while !input.is_char_boundary(end) {
+        end -= 1;
+    }
+    &input[..end]
+}
+
+// 处理单个文件名或目录名：替换不允许的字符，去掉结尾的点和空格，避开保留名，限制字节数
+pub fn sanitize_name(input: &str, rules: NameRules) -> String {
+    let replaced: String = input
+        .chars()
+        .map(|c| match c {
+            '/' | '\0' => '_',
+            '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' if rules == NameRules::Windows => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trim = |name: &str| -> String {
+        match rules {
+            NameRules::Windows => name.trim().trim_end_matches('.').trim_end().to_string(),
+            NameRules::Unix => name.to_string(),
+        }
+    };
+    let mut name = trim(truncate_bytes(&trim(&replaced), MAX_NAME_BYTES));
+    if rules == NameRules::Windows {
+        // CON 和 CON.jpg 都不能使用
+        let stem_len = name.find('.').unwrap_or(name.len());
+        if WINDOWS_RESERVED.contains(&name[..stem_len].to_uppercase().as_str()) {
+            name.insert(stem_len, '_');
+        }
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        name = String::from("_");
+    }
+    name
+}
+
+// 处理同一目录下的多个名称，处理后重名的按顺序加 _2 _3 后缀，windows 规则下不区分大小写
+pub fn unique_names(names: &Vec<String>, rules: NameRules) -> Vec<String> {
+    let key = |name: &str| match rules {
+        NameRules::Windows => name.to_lowercase(),
+        NameRules::Unix => name.to_string(),
+    };
+    let mut used: HashSet<String> = HashSet::new();
+    names
+        .iter()
+        .map(|name| {
+            let base = sanitize_name(name, rules);
+            let mut candidate = base.clone();
+            let mut n = 2;
+            while !used.insert(key(&candidate)) {
+                let suffix = format!("_{}", n);
+                candidate = format!(
+                    "{}{}",
+                    truncate_bytes(&base, MAX_NAME_BYTES - suffix.len()),
+                    suffix
+                );
+                n += 1;
+            }
+            candidate
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_decimal_chapter() {
+        let key = parse_chapter_number("第10.5话");
+        assert_eq!(key.chapter, Some(10.5));
+        assert!(parse_chapter_number("第10话") < key);
+        assert!(key < parse_chapter_number("第11话"));
+    }
+
+    #[test]
+    fn parse_chinese_numeral() {
+        assert_eq!(parse_chapter_number("第十二话").chapter, Some(12.0));
+        assert_eq!(parse_chapter_number("第１２话").chapter, Some(12.0));
+        assert_eq!(
+            parse_chapter_number("一之濑的日常 第3话").chapter,
+            Some(3.0)
+        );
+    }
+
+    #[test]
+    fn parse_extra() {
+        let extra = parse_chapter_number("番外");
+        let extra2 = parse_chapter_number("番外2");
+        assert!(extra.extra && extra2.extra);
+        assert_eq!(extra2.chapter, Some(2.0));
+        assert!(extra < extra2);
+        assert!(parse_chapter_number("第100话") < extra);
+    }
+
+    #[test]
+    fn parse_volume_and_chapter() {
+        let key = parse_chapter_number("第2卷第3话");
+        assert_eq!((key.volume, key.chapter), (Some(2.0), Some(3.0)));
+        let key = parse_chapter_number("Vol.2 Ch.10.5");
+        assert_eq!((key.volume, key.chapter), (Some(2.0), Some(10.5)));
+        assert!(parse_chapter_number("第1卷第9话") < parse_chapter_number("第2卷第1话"));
+        let key = parse_chapter_number("rev 3");
+        assert_eq!((key.volume, key.chapter), (None, Some(3.0)));
+    }
 }